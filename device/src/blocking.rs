@@ -0,0 +1,348 @@
+use core::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use async_std::task;
+use futures::{
+    channel::{mpsc, oneshot},
+    executor,
+    future::{self, BoxFuture},
+    lock::{Mutex, OwnedMutexGuard},
+    stream::{self, BoxStream},
+    SinkExt, StreamExt,
+};
+
+use crate::{
+    cache::StatusCache,
+    trigger::Source,
+    util::{EchoDevice, ScpiDevice, SimpleDevice},
+    Device, DeviceError, ExecuteOutput,
+};
+
+/// Response returned by [AsyncDevice::execute_async]
+pub struct AsyncOutput {
+    /// Total length of response as reported by [ExecuteOutput::len]
+    pub len: usize,
+    /// Response chunks, ends when the response is complete
+    pub chunks: BoxStream<'static, Vec<u8>>,
+}
+
+impl AsyncOutput {
+    /// Append response to `buf`
+    pub async fn extend_into(mut self, buf: &mut Vec<u8>) {
+        buf.reserve(self.len);
+        while let Some(chunk) = self.chunks.next().await {
+            buf.extend_from_slice(&chunk);
+        }
+    }
+}
+
+impl From<Vec<u8>> for AsyncOutput {
+    fn from(v: Vec<u8>) -> Self {
+        AsyncOutput {
+            len: v.len(),
+            chunks: stream::once(async move { v }).boxed(),
+        }
+    }
+}
+
+impl core::fmt::Debug for AsyncOutput {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncOutput")
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [Device] which can execute commands without blocking the executor.
+///
+/// The default implementation executes commands on the calling task, which is fine for devices
+/// responding immediately. A device performing slow I/O (e.g. a serial port) should implement
+/// [AsyncDevice::execute_async] natively or be wrapped in [Blocking], so it does not starve other
+/// sessions sharing the executor.
+pub trait AsyncDevice: Device + Send + 'static {
+    /// Execute a arbitrary command
+    fn execute_async(
+        mut device: OwnedMutexGuard<Self>,
+        cmd: Vec<u8>,
+    ) -> BoxFuture<'static, Option<AsyncOutput>> {
+        Box::pin(future::ready(execute_inline(&mut *device, cmd)))
+    }
+}
+
+impl<DEV: Device + Send + ?Sized + 'static> AsyncDevice for Box<DEV> {}
+
+impl AsyncDevice for EchoDevice {}
+
+impl AsyncDevice for SimpleDevice {}
+
+impl AsyncDevice for ScpiDevice {}
+
+impl<DEV: Device + Send + 'static> AsyncDevice for StatusCache<DEV> {}
+
+/// Position of `s` in `cmd`, if the response is borrowed from it
+fn range_in(s: &[u8], cmd: &[u8]) -> Option<Range<usize>> {
+    let start = (s.as_ptr() as usize).checked_sub(cmd.as_ptr() as usize)?;
+    (start + s.len() <= cmd.len()).then(|| start..start + s.len())
+}
+
+/// Reuse the command buffer for a response borrowed from it instead of copying
+fn reuse_cmd(mut cmd: Vec<u8>, range: Range<usize>) -> Vec<u8> {
+    cmd.truncate(range.end);
+    cmd.drain(..range.start);
+    cmd
+}
+
+/// Execute a command on the calling task, a streamed response is collected before returning
+fn execute_inline<DEV: Device + ?Sized>(device: &mut DEV, cmd: Vec<u8>) -> Option<AsyncOutput> {
+    let borrowed = match device.execute(&cmd)? {
+        ExecuteOutput::Owned(v) => return Some(v.into()),
+        ExecuteOutput::Borrowed(s) => range_in(s, &cmd).ok_or_else(|| s.to_vec()),
+        ExecuteOutput::Stream { len, chunks } => {
+            let chunks: Vec<_> = chunks.collect();
+            return Some(AsyncOutput {
+                len,
+                chunks: stream::iter(chunks).boxed(),
+            });
+        }
+    };
+    match borrowed {
+        Ok(range) => Some(reuse_cmd(cmd, range).into()),
+        Err(v) => Some(v.into()),
+    }
+}
+
+/// A device wrapper executing commands of a blocking [Device] on a separate thread.
+///
+/// A streamed response is also produced on the blocking thread and the device is kept locked
+/// until it has been produced completely.
+pub struct Blocking<DEV> {
+    inner: DEV,
+}
+
+impl<DEV> Blocking<DEV> {
+    pub fn new(inner: DEV) -> Self {
+        Self { inner }
+    }
+
+    pub fn new_arc(inner: DEV) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self::new(inner)))
+    }
+
+    pub fn inner(&self) -> &DEV {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut DEV {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> DEV {
+        self.inner
+    }
+}
+
+impl<DEV: Device> Device for Blocking<DEV> {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        self.inner.execute(cmd)
+    }
+
+    fn get_status(&mut self) -> Result<u8, DeviceError> {
+        self.inner.get_status()
+    }
+
+    fn trigger(&mut self, source: Source) -> Result<(), DeviceError> {
+        self.inner.trigger(source)
+    }
+
+    fn clear(&mut self) -> Result<(), DeviceError> {
+        self.inner.clear()
+    }
+
+    fn set_remote(&mut self, remote: bool) -> Result<(), DeviceError> {
+        self.inner.set_remote(remote)
+    }
+
+    fn set_local_lockout(&mut self, enable: bool) {
+        self.inner.set_local_lockout(enable)
+    }
+
+    fn docmd(&mut self, cmd: i32, data: &[u8]) -> Result<Vec<u8>, DeviceError> {
+        self.inner.docmd(cmd, data)
+    }
+}
+
+impl<DEV: Device + Send + 'static> AsyncDevice for Blocking<DEV> {
+    fn execute_async(
+        mut device: OwnedMutexGuard<Self>,
+        cmd: Vec<u8>,
+    ) -> BoxFuture<'static, Option<AsyncOutput>> {
+        Box::pin(async move {
+            let (output_tx, output_rx) = oneshot::channel();
+
+            task::spawn_blocking(move || {
+                let output = match panic::catch_unwind(AssertUnwindSafe(|| device.execute(&cmd))) {
+                    Ok(output) => output,
+                    Err(payload) => {
+                        let _ = output_tx.send(Err(payload));
+                        return;
+                    }
+                };
+                let (len, chunks) = match output {
+                    None => {
+                        drop(device);
+                        let _ = output_tx.send(Ok(None));
+                        return;
+                    }
+                    Some(ExecuteOutput::Owned(v)) => {
+                        drop(device);
+                        let _ = output_tx.send(Ok(Some(v.into())));
+                        return;
+                    }
+                    Some(ExecuteOutput::Borrowed(s)) => {
+                        let borrowed = range_in(s, &cmd).ok_or_else(|| s.to_vec());
+                        drop(output);
+                        let v = match borrowed {
+                            Ok(range) => reuse_cmd(cmd, range),
+                            Err(v) => v,
+                        };
+                        drop(device);
+                        let _ = output_tx.send(Ok(Some(v.into())));
+                        return;
+                    }
+                    Some(ExecuteOutput::Stream { len, chunks }) => (len, chunks),
+                };
+
+                let (mut chunk_tx, chunk_rx) = mpsc::channel(1);
+                let _ = output_tx.send(Ok(Some(AsyncOutput {
+                    len,
+                    chunks: chunk_rx.boxed(),
+                })));
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    for chunk in chunks {
+                        // Receiver dropped, response is no longer wanted
+                        if executor::block_on(chunk_tx.send(chunk)).is_err() {
+                            break;
+                        }
+                    }
+                }));
+                if res.is_err() {
+                    log::error!("Device panicked while producing a response");
+                }
+
+                // Release device before ending the response
+                drop(device);
+                drop(chunk_tx);
+            });
+
+            match output_rx.await {
+                Ok(Ok(output)) => output,
+                // Propagate panic to the session
+                Ok(Err(payload)) => panic::resume_unwind(payload),
+                Err(_) => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
+
+    use alloc::{boxed::Box, format, vec::Vec};
+    use futures::{join, lock::Mutex};
+
+    use super::{AsyncDevice, Blocking};
+    use crate::{trigger::Source, util::EchoDevice, Device, DeviceError, ExecuteOutput};
+
+    /// Device blocking in execute until signalled
+    struct BlockingDevice(mpsc::Receiver<()>);
+
+    impl Device for BlockingDevice {
+        fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            self.0.recv_timeout(Duration::from_secs(1)).ok()?;
+            match cmd {
+                b"DATA?" => Some(ExecuteOutput::Stream {
+                    len: 100 * 4,
+                    chunks: Box::new((0..100).map(|i| format!("{i:03},").into_bytes())),
+                }),
+                _ => Some(ExecuteOutput::Borrowed(cmd)),
+            }
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            Ok(0)
+        }
+
+        fn trigger(&mut self, _source: Source) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    async fn execute<DEV: AsyncDevice>(device: &Arc<Mutex<DEV>>, cmd: &[u8]) -> Option<Vec<u8>> {
+        let output =
+            AsyncDevice::execute_async(device.clone().lock_owned().await, cmd.to_vec()).await?;
+        let mut buf = Vec::new();
+        output.extend_into(&mut buf).await;
+        Some(buf)
+    }
+
+    #[test]
+    fn test_execute_does_not_block() {
+        let (tx, rx) = mpsc::channel();
+        let device = Blocking::new_arc(BlockingDevice(rx));
+
+        // Device is signalled by the same (single threaded) executor while executing
+        let (resp, _) = futures::executor::block_on(async {
+            join!(execute(&device, b"test"), async { tx.send(()).unwrap() })
+        });
+        assert_eq!(resp.as_deref(), Some(&b"test"[..]));
+    }
+
+    #[async_std::test]
+    async fn test_execute_stream() {
+        let (tx, rx) = mpsc::channel();
+        let device = Blocking::new_arc(BlockingDevice(rx));
+
+        tx.send(()).unwrap();
+        let resp = execute(&device, b"DATA?").await.unwrap();
+        let expected: Vec<u8> = (0..100)
+            .flat_map(|i| format!("{i:03},").into_bytes())
+            .collect();
+        assert_eq!(resp, expected);
+
+        // Device is released once the response has been produced
+        assert!(device.try_lock().is_some());
+    }
+
+    #[async_std::test]
+    async fn test_execute_inline() {
+        let device = Arc::new(Mutex::new(EchoDevice));
+        let resp = execute(&device, b"test").await;
+        assert_eq!(resp.as_deref(), Some(&b"test"[..]));
+    }
+
+    #[test]
+    fn test_reuse_cmd() {
+        let cmd = b"*IDN?;ECHO".to_vec();
+        let range = super::range_in(&cmd[6..], &cmd).unwrap();
+        let ptr = cmd.as_ptr();
+        let resp = super::reuse_cmd(cmd, range);
+        assert_eq!(resp, b"ECHO");
+        assert_eq!(resp.as_ptr(), ptr);
+
+        // Responses not borrowed from the command are copied
+        assert!(super::range_in(b"ECHO", b"*IDN?;ECHO").is_none());
+    }
+}
@@ -0,0 +1,156 @@
+use alloc::{sync::Arc, vec::Vec};
+use futures::lock::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{trigger::Source, Device, DeviceError, ExecuteOutput};
+
+/// A device wrapper which caches the status byte for a short window.
+///
+/// Sessions polling the status (e.g. HiSLIP `AsyncStatusQuery` or VXI-11 `device_readstb`) are
+/// served from the cache instead of each calling [Device::get_status] on the inner device.
+/// The cache is invalidated by anything which may change the status (execute, trigger, clear).
+pub struct StatusCache<DEV> {
+    inner: DEV,
+    window: Duration,
+    last: Option<(Instant, u8)>,
+}
+
+impl<DEV> StatusCache<DEV> {
+    /// Wrap `inner`, refreshing status at most once every `window`
+    pub fn new(inner: DEV, window: Duration) -> Self {
+        Self {
+            inner,
+            window,
+            last: None,
+        }
+    }
+
+    pub fn new_arc(inner: DEV, window: Duration) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self::new(inner, window)))
+    }
+
+    /// Force next status query to read from the inner device
+    pub fn invalidate(&mut self) {
+        self.last = None;
+    }
+
+    pub fn inner(&self) -> &DEV {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut DEV {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> DEV {
+        self.inner
+    }
+}
+
+impl<DEV: Device> Device for StatusCache<DEV> {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        self.invalidate();
+        self.inner.execute(cmd)
+    }
+
+    fn get_status(&mut self) -> Result<u8, DeviceError> {
+        match self.last {
+            Some((t, stb)) if t.elapsed() < self.window => Ok(stb),
+            _ => {
+                let stb = self.inner.get_status()?;
+                self.last = Some((Instant::now(), stb));
+                Ok(stb)
+            }
+        }
+    }
+
+    fn trigger(&mut self, source: Source) -> Result<(), DeviceError> {
+        self.invalidate();
+        self.inner.trigger(source)
+    }
+
+    fn clear(&mut self) -> Result<(), DeviceError> {
+        self.invalidate();
+        self.inner.clear()
+    }
+
+    fn set_remote(&mut self, remote: bool) -> Result<(), DeviceError> {
+        self.inner.set_remote(remote)
+    }
+
+    fn set_local_lockout(&mut self, enable: bool) {
+        self.inner.set_local_lockout(enable)
+    }
+
+    fn docmd(&mut self, cmd: i32, data: &[u8]) -> Result<Vec<u8>, DeviceError> {
+        self.invalidate();
+        self.inner.docmd(cmd, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::future::join_all;
+
+    use super::StatusCache;
+    use crate::{trigger::Source, Device, DeviceError, ExecuteOutput};
+
+    /// Device counting number of status reads
+    #[derive(Default)]
+    struct CountingDevice {
+        reads: usize,
+    }
+
+    impl Device for CountingDevice {
+        fn execute<'a>(&mut self, _cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            None
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            self.reads += 1;
+            Ok(0x10)
+        }
+
+        fn trigger(&mut self, _source: Source) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    #[async_std::test]
+    async fn test_coalesced_status() {
+        let device = StatusCache::new_arc(CountingDevice::default(), Duration::from_secs(10));
+
+        let queries = (0..100).map(|_| async {
+            let mut dev = device.lock().await;
+            dev.get_status().unwrap()
+        });
+        let stbs = join_all(queries).await;
+        assert!(stbs.iter().all(|stb| *stb == 0x10));
+
+        let mut dev = device.lock().await;
+        assert_eq!(dev.inner().reads, 1);
+
+        // Executing a command may change status
+        let _ = dev.execute(b"*CLS");
+        dev.get_status().unwrap();
+        assert_eq!(dev.inner().reads, 2);
+    }
+
+    #[test]
+    fn test_window_expired() {
+        let mut dev = StatusCache::new(CountingDevice::default(), Duration::ZERO);
+        dev.get_status().unwrap();
+        dev.get_status().unwrap();
+        assert_eq!(dev.inner().reads, 2);
+    }
+}
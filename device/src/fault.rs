@@ -0,0 +1,77 @@
+//! Synthetic faults which a server can inject into its responses.
+//!
+//! Useful for client authors testing timeout and error handling against a real server.
+use core::time::Duration;
+
+/// A fault to apply to a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Send response as normal
+    None,
+    /// Silently drop response
+    Drop,
+    /// Send a protocol error (or close connection if protocol has none) instead of response
+    Error,
+}
+
+/// Faults to inject into a server
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjection {
+    /// Delay before sending every response
+    pub delay: Option<Duration>,
+    /// Drop every n:th response, never if zero
+    pub drop_every: u32,
+    /// Replace every n:th response with an error, never if zero
+    pub error_every: u32,
+}
+
+impl FaultInjection {
+    /// Delay every response
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Drop every n:th response
+    pub fn drop_every(mut self, n: u32) -> Self {
+        self.drop_every = n;
+        self
+    }
+
+    /// Replace every n:th response with an error
+    pub fn error_every(mut self, n: u32) -> Self {
+        self.error_every = n;
+        self
+    }
+
+    /// Get fault to apply to the n:th response (counting from 1)
+    pub fn fault(&self, n: u32) -> Fault {
+        if n.checked_rem(self.drop_every) == Some(0) {
+            Fault::Drop
+        } else if n.checked_rem(self.error_every) == Some(0) {
+            Fault::Error
+        } else {
+            Fault::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fault, FaultInjection};
+
+    #[test]
+    fn test_fault() {
+        let faults = FaultInjection::default();
+        assert_eq!(faults.fault(1), Fault::None);
+        assert_eq!(faults.fault(2), Fault::None);
+
+        let faults = FaultInjection::default().drop_every(2).error_every(3);
+        assert_eq!(faults.fault(1), Fault::None);
+        assert_eq!(faults.fault(2), Fault::Drop);
+        assert_eq!(faults.fault(3), Fault::Error);
+        assert_eq!(faults.fault(4), Fault::Drop);
+        // Drop takes precedence
+        assert_eq!(faults.fault(6), Fault::Drop);
+    }
+}
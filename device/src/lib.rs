@@ -8,14 +8,30 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate alloc;
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{borrow::Cow, boxed::Box, vec::Vec};
+use core::{fmt, iter};
 use trigger::Source;
 
+#[cfg(feature = "async-std")]
+pub use blocking::{AsyncDevice, AsyncOutput, Blocking};
+
+/// Executing commands without blocking the executor
+#[cfg(feature = "async-std")]
+pub mod blocking;
+/// Status query coalescing
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
 #[cfg(feature = "experimental")]
 pub mod frontpanel;
 
 /// Instrument locking infrastructure
 pub mod lock;
+/// SCPI helpers
+pub mod scpi;
+/// Message size statistics
+pub mod stats;
 /// Internal device status/SRQ messaging channel
 pub mod status;
 /// Standard trigger sources
@@ -31,9 +47,105 @@ pub enum DeviceError {
     IoError,
 }
 
+/// Response returned by [Device::execute].
+///
+/// Lets a transport compute the response length (e.g. for a block header or XDR length) and
+/// write it out without first copying it into an intermediate buffer.
+pub enum ExecuteOutput<'a> {
+    /// Response owned by the device
+    Owned(Vec<u8>),
+    /// Response borrowed from the command
+    Borrowed(&'a [u8]),
+    /// Response produced in chunks, `len` is the total length of all chunks.
+    ///
+    /// Transports only use `len` for length headers and size hints, the response always ends when
+    /// `chunks` is exhausted.
+    Stream {
+        len: usize,
+        chunks: Box<dyn Iterator<Item = Vec<u8>> + Send + 'a>,
+    },
+}
+
+impl<'a> ExecuteOutput<'a> {
+    /// Total length of response
+    pub fn len(&self) -> usize {
+        match self {
+            ExecuteOutput::Owned(v) => v.len(),
+            ExecuteOutput::Borrowed(s) => s.len(),
+            ExecuteOutput::Stream { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over response in chunks. Owned and borrowed responses are returned as a single chunk.
+    pub fn into_chunks(self) -> Box<dyn Iterator<Item = Cow<'a, [u8]>> + Send + 'a> {
+        match self {
+            ExecuteOutput::Owned(v) => Box::new(iter::once(Cow::Owned(v))),
+            ExecuteOutput::Borrowed(s) => Box::new(iter::once(Cow::Borrowed(s))),
+            ExecuteOutput::Stream { chunks, .. } => Box::new(chunks.map(Cow::Owned)),
+        }
+    }
+
+    /// Append response to `buf`
+    pub fn extend_into(self, buf: &mut Vec<u8>) {
+        buf.reserve(self.len());
+        for chunk in self.into_chunks() {
+            buf.extend_from_slice(&chunk);
+        }
+    }
+
+    /// Collect response into a vector. Does not copy an owned response.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            ExecuteOutput::Owned(v) => v,
+            other => {
+                let mut buf = Vec::new();
+                other.extend_into(&mut buf);
+                buf
+            }
+        }
+    }
+
+    /// IEEE 488.2 definite length arbitrary block header (`#<digits><length>`) for this response.
+    pub fn block_header(&self) -> Vec<u8> {
+        let len = alloc::format!("{}", self.len());
+        let mut header = alloc::format!("#{}", len.len()).into_bytes();
+        header.extend_from_slice(len.as_bytes());
+        header
+    }
+}
+
+impl fmt::Debug for ExecuteOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteOutput::Owned(v) => f.debug_tuple("Owned").field(v).finish(),
+            ExecuteOutput::Borrowed(s) => f.debug_tuple("Borrowed").field(s).finish(),
+            ExecuteOutput::Stream { len, .. } => f
+                .debug_struct("Stream")
+                .field("len", len)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ExecuteOutput<'_> {
+    fn from(v: Vec<u8>) -> Self {
+        ExecuteOutput::Owned(v)
+    }
+}
+
+impl<'a> From<&'a [u8]> for ExecuteOutput<'a> {
+    fn from(s: &'a [u8]) -> Self {
+        ExecuteOutput::Borrowed(s)
+    }
+}
+
 pub trait Device {
     /// Execute a arbitrary command
-    fn execute(&mut self, cmd: &[u8]) -> Option<Vec<u8>>;
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>>;
 
     /// Return a current device status (STB) byte
     /// Some flags (such as MAV) will be ignored.
@@ -58,11 +170,19 @@ pub trait Device {
     fn set_local_lockout(&mut self, _enable: bool) {
         // Do nothing
     }
+
+    /// Execute a device specific command (VXI-11 `device_docmd`)
+    ///
+    /// `cmd` identifies the command and `data` holds its input. Returns the output data.
+    /// Devices not supporting any such commands should return [DeviceError::NotSupported].
+    fn docmd(&mut self, _cmd: i32, _data: &[u8]) -> Result<Vec<u8>, DeviceError> {
+        Err(DeviceError::NotSupported)
+    }
 }
 
 // Blanket proxy implementation for boxed devices
 impl<DEV: Device + ?Sized> Device for Box<DEV> {
-    fn execute(&mut self, cmd: &[u8]) -> Option<Vec<u8>> {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
         (**self).execute(cmd)
     }
 
@@ -85,4 +205,39 @@ impl<DEV: Device + ?Sized> Device for Box<DEV> {
     fn set_local_lockout(&mut self, enable: bool) {
         (**self).set_local_lockout(enable)
     }
+
+    fn docmd(&mut self, cmd: i32, data: &[u8]) -> Result<Vec<u8>, DeviceError> {
+        (**self).docmd(cmd, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    use super::ExecuteOutput;
+
+    fn block(output: ExecuteOutput) -> Vec<u8> {
+        let mut buf = output.block_header();
+        output.extend_into(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_block_output() {
+        let data = b"Hello world!";
+
+        let owned = ExecuteOutput::Owned(data.to_vec());
+        let borrowed = ExecuteOutput::Borrowed(&data[..]);
+        let stream = ExecuteOutput::Stream {
+            len: data.len(),
+            chunks: Box::new(vec![b"Hello".to_vec(), b" world!".to_vec()].into_iter()),
+        };
+
+        assert_eq!(block(owned), b"#212Hello world!");
+        assert_eq!(block(borrowed), b"#212Hello world!");
+        assert_eq!(block(stream), b"#212Hello world!");
+
+        assert_eq!(block(ExecuteOutput::Borrowed(b"")), b"#10");
+    }
 }
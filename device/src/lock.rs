@@ -1,7 +1,9 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::future::Future;
 use futures::channel::oneshot::{channel, Receiver, Sender};
+use futures::{pin_mut, FutureExt};
 
-pub use futures::lock::{Mutex, MutexGuard};
+pub use futures::lock::{Mutex, MutexGuard, OwnedMutexGuard};
 pub use spin::Mutex as SpinMutex;
 
 /// An error returned by a locking operation
@@ -32,6 +34,13 @@ pub enum SharedLockMode {
     Exclusive,
 }
 
+/// Locks kept by a disconnected handle, see [LockHandle::retain]
+struct Retained {
+    token: u32,
+    shared: bool,
+    exclusive: bool,
+}
+
 /// A lock controlling a device which may be accessed by multiple users.
 /// A user may acquire a shared or exclusive lock or try to access without any lock.
 pub struct SharedLock {
@@ -40,6 +49,9 @@ pub struct SharedLock {
     num_shared_locks: u32,
     exclusive_lock: bool,
     event: Vec<Sender<()>>,
+    retained: Vec<Retained>,
+    fair: bool,
+    queue: VecDeque<u32>,
 }
 
 impl SharedLock {
@@ -49,10 +61,24 @@ impl SharedLock {
             num_shared_locks: 0,
             exclusive_lock: false,
             event: Vec::new(),
+            retained: Vec::new(),
             id_counter: 1,
+            fair: false,
+            queue: VecDeque::new(),
         }))
     }
 
+    /// Same as [SharedLock::new] but exclusive locks are granted in the order they were requested.
+    ///
+    /// Handles waiting for an exclusive lock are queued and no other handle may acquire an exclusive lock
+    /// until the queue is empty, even if the lock is available when trying. A handle holding the shared
+    /// lock may still upgrade to an exclusive lock.
+    pub fn new_fair() -> Arc<SpinMutex<SharedLock>> {
+        let lock = Self::new();
+        lock.lock().fair = true;
+        lock
+    }
+
     /// Get the number of clients that share access to this lock.
     #[must_use]
     pub fn num_shared_locks(&self) -> u32 {
@@ -83,10 +109,68 @@ impl SharedLock {
         receiver
     }
 
+    /// Release locks previously retained by [LockHandle::retain] unless they have been reclaimed.
+    /// Returns `true` if the locks were released.
+    pub fn release_retained(&mut self, token: u32) -> bool {
+        if let Some(pos) = self.retained.iter().position(|r| r.token == token) {
+            let retained = self.retained.remove(pos);
+            let mut notify = retained.exclusive;
+            if retained.exclusive {
+                self.exclusive_lock = false;
+            }
+            if retained.shared {
+                self.num_shared_locks -= 1;
+                if self.num_shared_locks == 0 {
+                    self.shared_lock = None;
+                    notify = true;
+                }
+            }
+            if notify {
+                self.notify_release();
+            }
+            log::trace!(id=token; "Released retained shared");
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn next_id(&mut self) -> u32 {
         self.id_counter = self.id_counter.wrapping_add(1);
         self.id_counter
     }
+
+    /// Check if another handle is queued before `id`
+    fn queued_before(&self, id: u32) -> bool {
+        self.fair && self.queue.front().is_some_and(|front| *front != id)
+    }
+}
+
+/// A place in the queue for an exclusive lock, leaves the queue when dropped.
+struct QueueTicket {
+    parent: Arc<SpinMutex<SharedLock>>,
+    id: u32,
+}
+
+impl QueueTicket {
+    fn new(parent: Arc<SpinMutex<SharedLock>>, id: u32) -> Option<Self> {
+        let mut shared = parent.lock();
+        if !shared.fair || shared.queue.contains(&id) {
+            return None;
+        }
+        shared.queue.push_back(id);
+        drop(shared);
+        Some(Self { parent, id })
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        let mut shared = self.parent.lock();
+        shared.queue.retain(|id| *id != self.id);
+        // Let the next in line try again
+        shared.notify_release();
+    }
 }
 
 /// A handle to a locked resource.
@@ -163,6 +247,31 @@ impl<DEV> LockHandle<DEV> {
         }
     }
 
+    /// Same as [LockHandle::async_acquire] but gives up if `cancel` completes first.
+    ///
+    /// The error `cancel` resolves to is returned, typically [SharedLockError::Timeout] when
+    /// cancelled by a timer.
+    pub async fn async_acquire_cancellable<F>(
+        &mut self,
+        lockstr: &[u8],
+        cancel: F,
+    ) -> Result<(), SharedLockError>
+    where
+        F: Future<Output = SharedLockError>,
+    {
+        let acquire = self.async_acquire(lockstr).fuse();
+        let cancel = cancel.fuse();
+        pin_mut!(acquire, cancel);
+
+        futures::select! {
+            res = acquire => res,
+            err = cancel => {
+                log::trace!("Acquire cancelled: {err:?}");
+                Err(err)
+            }
+        }
+    }
+
     /// Try to acquire an exclusive lock
     /// Returns immediately once it ha polled the lock with success or error
     pub fn try_acquire_exclusive(&mut self) -> Result<(), SharedLockError> {
@@ -172,6 +281,11 @@ impl<DEV> LockHandle<DEV> {
 
         let mut shared = self.parent.lock();
 
+        // Shared holders may always upgrade, the queued handles are waiting for them to release
+        if !self.has_shared && shared.queued_before(self.id) {
+            return Err(SharedLockError::LockedByExclusive);
+        }
+
         match (shared.exclusive_lock, &shared.shared_lock) {
             // Current state: Unlocked
             (false, None) => {
@@ -200,13 +314,31 @@ impl<DEV> LockHandle<DEV> {
                 }
             }
             // Current state: Both locks
-            (true, Some(_)) => Err(SharedLockError::LockedByExclusive),
+            (true, Some(_)) => {
+                let pos = shared.retained.iter().position(|r| r.exclusive);
+                match pos {
+                    // Take over an exclusive lock kept by a disconnected handle sharing my lock
+                    Some(pos) if self.has_shared => {
+                        let retained = &mut shared.retained[pos];
+                        retained.exclusive = false;
+                        log::trace!(id=self.id; "Reclaimed exclusive retained by {}", retained.token);
+                        if !retained.shared {
+                            shared.retained.remove(pos);
+                        }
+                        self.has_exclusive = true;
+                        shared.notify_acquired();
+                        Ok(())
+                    }
+                    _ => Err(SharedLockError::LockedByExclusive),
+                }
+            }
         }
     }
 
     /// Acquire an exclusive lock asynchronously
     pub async fn async_acquire_exclusive(&mut self) -> Result<(), SharedLockError> {
         let mut listener = None;
+        let mut ticket = None;
 
         loop {
             match self.try_acquire_exclusive() {
@@ -214,6 +346,9 @@ impl<DEV> LockHandle<DEV> {
                 Err(SharedLockError::LockedByShared) | Err(SharedLockError::LockedByExclusive) => {
                     match listener.take() {
                         None => {
+                            // Queue up if fair
+                            ticket =
+                                ticket.or_else(|| QueueTicket::new(self.parent.clone(), self.id));
                             // Start listening and then try locking again.
                             let mut shared = self.parent.lock();
                             listener = Some(shared.listen());
@@ -263,8 +398,19 @@ impl<DEV> LockHandle<DEV> {
             // Current state: Shared lock or both locks
             (_, Some(key)) => {
                 if key == lockstr {
-                    shared.num_shared_locks += 1;
                     self.has_shared = true;
+                    if let Some(pos) = shared.retained.iter().position(|r| r.shared) {
+                        // Take over a shared lock kept by a disconnected handle,
+                        // a retained exclusive lock is only handed over by try_acquire_exclusive
+                        let retained = &mut shared.retained[pos];
+                        retained.shared = false;
+                        log::trace!(id=self.id; "Reclaimed shared retained by {}", retained.token);
+                        if !retained.exclusive {
+                            shared.retained.remove(pos);
+                        }
+                    } else {
+                        shared.num_shared_locks += 1;
+                    }
 
                     //
                     log::trace!(id=self.id; "Acquired shared (previously shared)");
@@ -305,8 +451,8 @@ impl<DEV> LockHandle<DEV> {
         }
     }
 
-    // Release any locks being held.
-    // Returns an error if no locks are held by this handle
+    /// Release any locks being held.
+    /// Returns [SharedLockError::AlreadyUnlocked] if no locks are held by this handle
     pub fn try_release(&mut self) -> Result<SharedLockMode, SharedLockError> {
         let mut shared = self.parent.lock();
         let mut res = Err(SharedLockError::AlreadyUnlocked);
@@ -341,17 +487,43 @@ impl<DEV> LockHandle<DEV> {
         res
     }
 
+    /// Keep the locks held by this handle after it has been released or dropped.
+    /// The shared lock may be reclaimed by another handle acquiring a shared lock with the same lock string.
+    /// An exclusive lock held together with it is only handed over when such a handle also acquires an
+    /// exclusive lock, until then it keeps blocking everyone.
+    ///
+    /// Returns a token to pass to [SharedLock::release_retained] or `None` if no shared lock is held.
+    pub fn retain(&mut self) -> Option<u32> {
+        if !self.has_shared {
+            return None;
+        }
+        self.parent.lock().retained.push(Retained {
+            token: self.id,
+            shared: true,
+            exclusive: self.has_exclusive,
+        });
+        self.has_shared = false;
+        self.has_exclusive = false;
+        log::trace!(id=self.id; "Retained shared");
+        Some(self.id)
+    }
+
+    /// Get the shared lock this handle belongs to
+    pub fn shared_lock(&self) -> Arc<SpinMutex<SharedLock>> {
+        self.parent.clone()
+    }
+
     /// Check if the shared lock is available and then lock
-    pub fn try_lock(&self) -> Result<MutexGuard<DEV>, SharedLockError> {
+    pub fn try_lock(&self) -> Result<OwnedMutexGuard<DEV>, SharedLockError> {
         // Check any active locks
         self.can_lock()?;
         // Lock device and return a guard
-        self.device.try_lock().ok_or(SharedLockError::Busy)
+        self.device.try_lock_owned().ok_or(SharedLockError::Busy)
     }
 
     /// Lock device if allowed
     ///
-    pub async fn async_lock(&self) -> Result<MutexGuard<DEV>, SharedLockError> {
+    pub async fn async_lock(&self) -> Result<OwnedMutexGuard<DEV>, SharedLockError> {
         let mut listener = None;
 
         loop {
@@ -365,7 +537,7 @@ impl<DEV> LockHandle<DEV> {
 
                     futures::select! {
                         // Device acquired
-                        guard = self.device.lock() => {
+                        guard = self.device.clone().lock_owned() => {
                             log::trace!(id=self.id; "Locked!");
                             return Ok(guard)
                         },
@@ -443,16 +615,16 @@ impl<DEV> RemoteLockHandle<DEV> {
     }
 
     /// Check if the shared lock is available and then lock
-    pub async fn try_lock(&self) -> Result<MutexGuard<DEV>, SharedLockError> {
+    pub async fn try_lock(&self) -> Result<OwnedMutexGuard<DEV>, SharedLockError> {
         // Check any active locks
         self.can_lock()?;
         // Lock device and return a guard
-        self.device.try_lock().ok_or(SharedLockError::Busy)
+        self.device.try_lock_owned().ok_or(SharedLockError::Busy)
     }
 
     /// Wait for device becoming onlocked (or handle acquiring a lock) and available
     ///
-    pub async fn async_lock(&self) -> Result<MutexGuard<DEV>, SharedLockError> {
+    pub async fn async_lock(&self) -> Result<OwnedMutexGuard<DEV>, SharedLockError> {
         let mut listener = None;
 
         loop {
@@ -469,7 +641,7 @@ impl<DEV> RemoteLockHandle<DEV> {
 
                     futures::select! {
                         // Device acquired
-                        guard = self.device.lock() => {
+                        guard = self.device.clone().lock_owned() => {
                             log::trace!("Locked!");
                             return Ok(guard)
                         },
@@ -503,6 +675,30 @@ impl<DEV> RemoteLockHandle<DEV> {
         }
     }
 
+    /// Same as [RemoteLockHandle::async_lock] but gives up if `cancel` completes first.
+    ///
+    /// The error `cancel` resolves to is returned, typically [SharedLockError::Aborted] when
+    /// cancelled by a device clear or [SharedLockError::Timeout] when cancelled by a timer.
+    pub async fn async_lock_cancellable<F>(
+        &self,
+        cancel: F,
+    ) -> Result<OwnedMutexGuard<DEV>, SharedLockError>
+    where
+        F: Future<Output = SharedLockError>,
+    {
+        let lock = self.async_lock().fuse();
+        let cancel = cancel.fuse();
+        pin_mut!(lock, cancel);
+
+        futures::select! {
+            res = lock => res,
+            err = cancel => {
+                log::trace!("Lock cancelled: {err:?}");
+                Err(err)
+            }
+        }
+    }
+
     pub fn can_lock(&self) -> Result<(), SharedLockError> {
         let remote = self.handle.lock();
         remote.can_lock()
@@ -521,6 +717,7 @@ impl<DEV> RemoteLockHandle<DEV> {
     }
     pub async fn async_acquire(&self, lockstr: &[u8]) -> Result<(), SharedLockError> {
         let mut listener = None;
+        let mut ticket = None;
 
         loop {
             match self.try_acquire(lockstr) {
@@ -530,6 +727,12 @@ impl<DEV> RemoteLockHandle<DEV> {
                         None => {
                             let remote = self.handle.lock();
 
+                            // Queue up for an exclusive lock if fair
+                            if lockstr.is_empty() {
+                                ticket = ticket
+                                    .or_else(|| QueueTicket::new(remote.parent.clone(), remote.id));
+                            }
+
                             // Start listening and then try locking again.
                             let mut shared = remote.parent.lock();
                             listener = Some(shared.listen());
@@ -553,10 +756,15 @@ impl<DEV> RemoteLockHandle<DEV> {
 #[cfg(test)]
 mod tests {
 
-    use super::{LockHandle, SharedLock, SpinMutex};
+    use super::{LockHandle, SharedLock, SharedLockError, SharedLockMode, SpinMutex};
     use crate::{lock::RemoteLockHandle, util::EchoDevice};
-    use async_std::{sync::Arc, task::yield_now};
-    use futures::{join, lock::Mutex};
+    use alloc::vec::Vec;
+    use async_std::{
+        sync::Arc,
+        task::{sleep, yield_now},
+    };
+    use core::{cell::RefCell, time::Duration};
+    use futures::{channel::oneshot, future, join, lock::Mutex, FutureExt};
 
     #[test]
     fn test_exclusive() {
@@ -596,6 +804,7 @@ mod tests {
 
         // Cannot acquire a shared lock "bar" because "foo" is locked
         assert!(handle2.try_acquire_shared(b"bar").is_err());
+        assert_eq!(handle3.lock_info(), (false, 2));
 
         // Only "foo" handles may lock
         assert!(handle1.can_lock().is_ok());
@@ -603,6 +812,86 @@ mod tests {
         assert!(handle3.can_lock().is_err());
     }
 
+    #[test]
+    fn test_shared_retained() {
+        let shared = SharedLock::new();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        // Client acquires a shared lock "foo" and disconnects
+        let mut handle1 = LockHandle::new(shared.clone(), device.clone());
+        assert!(handle1.try_acquire_shared(b"foo").is_ok());
+        let token = handle1.retain().unwrap();
+        drop(handle1);
+
+        // Lock is still held
+        let mut handle2 = LockHandle::new(shared.clone(), device.clone());
+        assert!(handle2.can_lock().is_err());
+        assert!(handle2.try_acquire_shared(b"bar").is_err());
+
+        // Client reconnects and reclaims the lock
+        assert!(handle2.try_acquire_shared(b"foo").is_ok());
+        assert_eq!(shared.lock().num_shared_locks(), 1);
+
+        // Grace period expires, reclaimed lock is kept
+        assert!(!shared.lock().release_retained(token));
+        assert!(handle2.can_lock().is_ok());
+        assert!(handle2.try_release().is_ok());
+        assert_eq!(shared.lock().num_shared_locks(), 0);
+
+        // Lock is released if not reclaimed within grace period
+        assert!(handle2.try_acquire_shared(b"foo").is_ok());
+        let token = handle2.retain().unwrap();
+        let handle3 = LockHandle::new(shared.clone(), device.clone());
+        assert!(handle3.can_lock().is_err());
+        assert!(shared.lock().release_retained(token));
+        assert!(handle3.can_lock().is_ok());
+    }
+
+    #[test]
+    fn test_exclusive_retained() {
+        let shared = SharedLock::new();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        // Client upgrades shared lock "foo" to exclusive and disconnects
+        let mut handle1 = LockHandle::new(shared.clone(), device.clone());
+        assert!(handle1.try_acquire_shared(b"foo").is_ok());
+        assert!(handle1.try_acquire_exclusive().is_ok());
+        let token = handle1.retain().unwrap();
+        drop(handle1);
+
+        // Exclusive lock is still held
+        let mut handle2 = LockHandle::new(shared.clone(), device.clone());
+        assert_eq!(handle2.lock_info(), (true, 1));
+        assert!(handle2.try_acquire_exclusive().is_err());
+
+        // Client reconnects and reclaims the shared lock only
+        let mut handle3 = LockHandle::new(shared.clone(), device.clone());
+        assert!(handle3.try_acquire_shared(b"foo").is_ok());
+        assert!(!handle3.has_exclusive());
+        assert!(handle3.can_lock().is_err());
+        assert_eq!(handle2.lock_info(), (true, 1));
+
+        // Exclusive lock is handed over when asked for
+        assert!(handle2.try_acquire_exclusive().is_err());
+        assert!(handle3.try_acquire_exclusive().is_ok());
+        assert!(handle3.can_lock().is_ok());
+        assert!(!shared.lock().release_retained(token));
+        assert!(matches!(
+            handle3.try_release(),
+            Ok(SharedLockMode::Exclusive)
+        ));
+        assert_eq!(handle2.lock_info(), (false, 0));
+
+        // Exclusive lock is released if not reclaimed within grace period
+        assert!(handle3.try_acquire_shared(b"foo").is_ok());
+        assert!(handle3.try_acquire_exclusive().is_ok());
+        let token = handle3.retain().unwrap();
+        assert!(handle3.try_acquire_shared(b"foo").is_ok());
+        assert!(shared.lock().release_retained(token));
+        assert_eq!(handle2.lock_info(), (false, 1));
+        assert!(handle3.can_lock().is_ok());
+    }
+
     #[test]
     fn test_shared_upgrade() {
         let shared = SharedLock::new();
@@ -621,6 +910,7 @@ mod tests {
 
         // Handle1 makes its shared lock exclusive
         assert!(handle1.try_acquire_exclusive().is_ok());
+        assert_eq!(handle2.lock_info(), (true, 2));
 
         // Only handle1 can lock using its exclusive
         assert!(handle1.can_lock().is_ok());
@@ -629,7 +919,8 @@ mod tests {
         // Handle1 releases its locks
         assert!(handle1.try_release().is_ok());
 
-        // Both handles have a shared lock
+        // Handle2 still has its shared lock
+        assert_eq!(handle2.lock_info(), (false, 1));
         assert!(handle1.can_lock().is_err());
         assert!(handle2.can_lock().is_ok());
     }
@@ -658,6 +949,129 @@ mod tests {
 
         // Handle1 can lock again
         assert!(handle1.can_lock().is_ok());
+
+        // A dropped shared lock is released as well
+        {
+            let mut handle2 = LockHandle::new(shared.clone(), device.clone());
+            assert!(handle2.try_acquire_shared(b"foo").is_ok());
+            assert_eq!(handle1.lock_info(), (false, 1));
+        }
+        assert_eq!(handle1.lock_info(), (false, 0));
+
+        // A fresh handle can acquire an exclusive lock
+        let mut handle3 = LockHandle::new(shared.clone(), device.clone());
+        assert!(handle3.try_acquire_exclusive().is_ok());
+    }
+
+    #[test]
+    fn test_release_unlocked() {
+        let shared = SharedLock::new();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        let mut handle1 = LockHandle::new(shared.clone(), device.clone());
+
+        // Nothing to release
+        assert!(matches!(
+            handle1.try_release(),
+            Err(SharedLockError::AlreadyUnlocked)
+        ));
+
+        // Released once
+        handle1.try_acquire_exclusive().unwrap();
+        assert!(matches!(
+            handle1.try_release(),
+            Ok(SharedLockMode::Exclusive)
+        ));
+        assert!(matches!(
+            handle1.try_release(),
+            Err(SharedLockError::AlreadyUnlocked)
+        ));
+    }
+
+    #[test]
+    fn test_busy() {
+        let shared = SharedLock::new();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        let handle1 = LockHandle::new(shared.clone(), device.clone());
+        let handle2 = LockHandle::new(shared.clone(), device.clone());
+
+        // Handle1 is using the device without holding a lock
+        let guard = handle1.try_lock().unwrap();
+        assert!(handle2.can_lock().is_ok());
+        assert!(matches!(handle2.try_lock(), Err(SharedLockError::Busy)));
+
+        // Available once handle1 is done
+        drop(guard);
+        assert!(handle2.try_lock().is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_fair_exclusive() {
+        let shared = SharedLock::new_fair();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        let mut handle0 = LockHandle::new(shared.clone(), device.clone());
+        let mut late = LockHandle::new(shared.clone(), device.clone());
+        handle0.try_acquire_exclusive().unwrap();
+
+        let order = RefCell::new(Vec::new());
+        let waiter = |n: u32| {
+            let mut handle = LockHandle::new(shared.clone(), device.clone());
+            let order = &order;
+            async move {
+                handle.async_acquire_exclusive().await.unwrap();
+                order.borrow_mut().push(n);
+                yield_now().await;
+                handle.try_release().unwrap();
+            }
+        };
+
+        join!(waiter(1), waiter(2), waiter(3), async {
+            yield_now().await;
+            handle0.try_release().unwrap();
+
+            // Lock is available but others are queued before
+            assert!(matches!(
+                late.try_acquire_exclusive(),
+                Err(SharedLockError::LockedByExclusive)
+            ));
+        });
+
+        assert_eq!(order.into_inner(), [1, 2, 3]);
+        assert!(late.try_acquire_exclusive().is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_fair_upgrade() {
+        let shared = SharedLock::new_fair();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        let mut holder = LockHandle::new(shared.clone(), device.clone());
+        let mut waiter = LockHandle::new(shared.clone(), device.clone());
+        holder.try_acquire_shared(b"foo").unwrap();
+
+        join!(
+            async {
+                waiter.async_acquire_exclusive().await.unwrap();
+                waiter.try_release().unwrap();
+            },
+            async {
+                yield_now().await;
+                assert_eq!(shared.lock().queue.len(), 1);
+
+                // Upgrade is not queued behind a handle waiting for the shared lock
+                async_std::future::timeout(
+                    Duration::from_secs(1),
+                    holder.async_acquire_exclusive(),
+                )
+                .await
+                .expect("upgrade deadlocked")
+                .unwrap();
+                assert_eq!(holder.lock_info(), (true, 1));
+                holder.try_release().unwrap();
+            }
+        );
     }
 
     #[test]
@@ -686,6 +1100,64 @@ mod tests {
         assert!(remote2.can_lock().is_err());
     }
 
+    #[async_std::test]
+    async fn test_remote_lock_cancelled() {
+        let shared = SharedLock::new();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        let handle1 = Arc::new(SpinMutex::new(LockHandle::new(
+            shared.clone(),
+            device.clone(),
+        )));
+        let remote1 = RemoteLockHandle::new(handle1.clone());
+
+        let mut handle2 = LockHandle::new(shared.clone(), device.clone());
+        handle2.try_acquire_exclusive().unwrap();
+
+        // Locked by handle2, waiting is aborted (e.g. by a device clear)
+        let (abort, aborted) = oneshot::channel::<()>();
+        let res = join!(
+            remote1.async_lock_cancellable(aborted.map(|_| SharedLockError::Aborted)),
+            async move {
+                yield_now().await;
+                abort.send(()).unwrap();
+            }
+        );
+        assert!(matches!(res.0, Err(SharedLockError::Aborted)));
+
+        // Can lock once handle2 has released its lock
+        handle2.try_release().unwrap();
+        let res = remote1
+            .async_lock_cancellable(future::pending::<SharedLockError>())
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_acquire_timeout() {
+        let shared = SharedLock::new();
+        let device = Arc::new(Mutex::new(EchoDevice));
+
+        let mut handle1 = LockHandle::new(shared.clone(), device.clone());
+        let mut handle2 = LockHandle::new(shared.clone(), device.clone());
+        handle1.try_acquire_exclusive().unwrap();
+
+        // Locked by handle1, times out
+        let timeout = sleep(Duration::from_millis(10)).map(|_| SharedLockError::Timeout);
+        let res = handle2.async_acquire_cancellable(b"", timeout).await;
+        assert!(matches!(res, Err(SharedLockError::Timeout)));
+        assert!(!handle2.has_exclusive());
+
+        // Handle1 releases its lock before the timeout
+        let timeout = sleep(Duration::from_secs(10)).map(|_| SharedLockError::Timeout);
+        let res = join!(handle2.async_acquire_cancellable(b"", timeout), async {
+            yield_now().await;
+            handle1.try_release().unwrap();
+        });
+        assert!(res.0.is_ok());
+        assert!(handle2.has_exclusive());
+    }
+
     //#[cfg(std)]
     #[async_std::test]
     async fn test_shared_handle_async() {
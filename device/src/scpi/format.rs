@@ -0,0 +1,87 @@
+//! Format response data according to SCPI/IEEE 488.2 rules.
+//!
+//! Integers are formatted as `<NR1>`, reals as `<NR3>` and booleans as `1`/`0`.
+//! Infinity and NaN are represented with the special values defined in SCPI-99 chapter 7.2.1.
+use alloc::{format, string::String, vec::Vec};
+
+/// Positive infinity, `9.9E+37`
+pub const INFINITY: &str = "9.9E+37";
+/// Negative infinity, `-9.9E+37`
+pub const NEG_INFINITY: &str = "-9.9E+37";
+/// Not a number, `9.91E+37`
+pub const NAN: &str = "9.91E+37";
+
+/// Format an integer as `<NR1>`
+pub fn format_int(value: i64) -> String {
+    format!("{value}")
+}
+
+/// Format a boolean as `1` or `0`
+pub fn format_bool(value: bool) -> String {
+    String::from(if value { "1" } else { "0" })
+}
+
+/// Format a real as `<NR3>`, e.g. `1.5E-3`
+pub fn format_real(value: f64) -> String {
+    if value.is_nan() {
+        return String::from(NAN);
+    } else if value.is_infinite() {
+        return String::from(if value > 0.0 { INFINITY } else { NEG_INFINITY });
+    }
+
+    // Rust formats as `1.5E-3` or `1E3`, add decimal point and exponent sign
+    let s = format!("{value:E}");
+    let (mantissa, exponent) = s.split_once('E').unwrap_or((&s, "0"));
+    let point = if mantissa.contains('.') { "" } else { ".0" };
+    if exponent.starts_with('-') {
+        format!("{mantissa}{point}E{exponent}")
+    } else {
+        format!("{mantissa}{point}E+{exponent}")
+    }
+}
+
+/// Format a comma separated list of reals
+pub fn format_list(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|v| format_real(*v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_real() {
+        assert_eq!(format_real(1.5e-3), "1.5E-3");
+        assert_eq!(format_real(1000.0), "1.0E+3");
+        assert_eq!(format_real(-2.25), "-2.25E+0");
+        assert_eq!(format_real(0.0), "0.0E+0");
+    }
+
+    #[test]
+    fn test_format_special() {
+        assert_eq!(format_real(f64::NAN), "9.91E+37");
+        assert_eq!(format_real(f64::INFINITY), "9.9E+37");
+        assert_eq!(format_real(f64::NEG_INFINITY), "-9.9E+37");
+    }
+
+    #[test]
+    fn test_format_int_bool() {
+        assert_eq!(format_int(-42), "-42");
+        assert_eq!(format_bool(true), "1");
+        assert_eq!(format_bool(false), "0");
+    }
+
+    #[test]
+    fn test_format_list() {
+        assert_eq!(format_list(&[]), "");
+        assert_eq!(format_list(&[1.0]), "1.0E+0");
+        assert_eq!(
+            format_list(&[1.0, f64::NAN, -0.5]),
+            "1.0E+0,9.91E+37,-5.0E-1"
+        );
+    }
+}
@@ -0,0 +1,2 @@
+/// SCPI response data formatting
+pub mod format;
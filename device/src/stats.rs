@@ -0,0 +1,117 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of histogram buckets, see [SizeHistogram::bucket].
+pub const BUCKETS: usize = 33;
+
+/// Lock-free histogram of message sizes using power of two buckets.
+#[derive(Debug)]
+pub struct SizeHistogram {
+    count: AtomicUsize,
+    min: AtomicUsize,
+    max: AtomicUsize,
+    buckets: [AtomicUsize; BUCKETS],
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SizeHistogram {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            count: AtomicUsize::new(0),
+            min: AtomicUsize::new(usize::MAX),
+            max: AtomicUsize::new(0),
+            buckets: [ZERO; BUCKETS],
+        }
+    }
+
+    /// Bucket index of `size`.
+    ///
+    /// Bucket 0 holds empty messages and bucket `n` holds sizes in `2^(n-1)..2^n`.
+    /// The last bucket also holds anything larger.
+    pub fn bucket(size: usize) -> usize {
+        ((usize::BITS - size.leading_zeros()) as usize).min(BUCKETS - 1)
+    }
+
+    /// Record a message of `size` bytes
+    pub fn record(&self, size: usize) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.min.fetch_min(size, Ordering::Relaxed);
+        self.max.fetch_max(size, Ordering::Relaxed);
+        self.buckets[Self::bucket(size)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of recorded messages
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Smallest recorded message, `None` if nothing has been recorded
+    pub fn min(&self) -> Option<usize> {
+        Some(self.min.load(Ordering::Relaxed)).filter(|_| self.count() > 0)
+    }
+
+    /// Largest recorded message, `None` if nothing has been recorded
+    pub fn max(&self) -> Option<usize> {
+        Some(self.max.load(Ordering::Relaxed)).filter(|_| self.count() > 0)
+    }
+
+    /// Snapshot of all buckets
+    pub fn buckets(&self) -> [usize; BUCKETS] {
+        let mut buckets = [0; BUCKETS];
+        for (dst, src) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+        buckets
+    }
+}
+
+/// Inbound and outbound message size statistics of a server.
+///
+/// Share an `Arc<MessageStats>` with a server to collect statistics and read them back at any time.
+#[derive(Debug, Default)]
+pub struct MessageStats {
+    /// Sizes of messages received from clients
+    pub inbound: SizeHistogram,
+    /// Sizes of messages sent to clients
+    pub outbound: SizeHistogram,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SizeHistogram;
+
+    #[test]
+    fn test_histogram() {
+        let hist = SizeHistogram::new();
+        assert_eq!(hist.min(), None);
+        assert_eq!(hist.max(), None);
+
+        for size in [0, 1, 3, 4, 7, 8, 1024, 1500] {
+            hist.record(size);
+        }
+
+        assert_eq!(hist.count(), 8);
+        assert_eq!(hist.min(), Some(0));
+        assert_eq!(hist.max(), Some(1500));
+
+        let buckets = hist.buckets();
+        assert_eq!(buckets[0], 1); // 0
+        assert_eq!(buckets[1], 1); // 1
+        assert_eq!(buckets[2], 1); // 2..4
+        assert_eq!(buckets[3], 2); // 4..8
+        assert_eq!(buckets[4], 1); // 8..16
+        assert_eq!(buckets[11], 2); // 1024..2048
+        assert_eq!(buckets.iter().sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn test_bucket_saturates() {
+        assert_eq!(SizeHistogram::bucket(usize::MAX), super::BUCKETS - 1);
+    }
+}
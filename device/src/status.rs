@@ -6,6 +6,7 @@ use spin::Mutex;
 #[derive(Clone)]
 pub struct Sender {
     senders: Arc<Mutex<Vec<mpsc::Sender<u8>>>>,
+    latest: Arc<Mutex<Option<u8>>>,
 }
 
 pub type Receiver = mpsc::Receiver<u8>;
@@ -20,11 +21,13 @@ impl Sender {
     pub fn new() -> Self {
         Self {
             senders: Arc::new(Mutex::new(Vec::new())),
+            latest: Arc::new(Mutex::new(None)),
         }
     }
 
     pub fn send_status(&mut self, status: u8) {
         let mut senders = self.senders.lock();
+        *self.latest.lock() = Some(status);
         senders.retain_mut(|sender| {
             let _ = sender.try_send(status);
             // Delete from senders if closed
@@ -38,12 +41,29 @@ impl Sender {
         senders.push(sender);
         receiver
     }
+
+    /// Get the most recently sent status, if any.
+    pub fn latest(&self) -> Option<u8> {
+        *self.latest.lock()
+    }
+
+    /// Same as [Sender::get_new_receiver] but the receiver will first receive the most recently sent status, if any.
+    pub fn get_new_receiver_with_latest(&mut self) -> Receiver {
+        let mut senders = self.senders.lock();
+        let (mut sender, receiver) = mpsc::channel(1);
+        if let Some(status) = self.latest() {
+            let _ = sender.try_send(status);
+        }
+        senders.push(sender);
+        receiver
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use futures::{FutureExt, StreamExt};
 
     #[test]
     fn test_sender() {
@@ -60,4 +80,29 @@ mod tests {
         assert_eq!(receiver2.try_next().unwrap(), Some(1));
         assert!(receiver2.try_next().is_err());
     }
+
+    #[test]
+    fn test_latest() {
+        let mut sender = Sender::new();
+        assert_eq!(sender.latest(), None);
+        let mut receiver1 = sender.get_new_receiver_with_latest();
+        assert!(receiver1.next().now_or_never().is_none());
+
+        sender.send_status(1);
+        sender.send_status(2);
+        assert_eq!(sender.latest(), Some(2));
+
+        // Receivers created after the status was sent
+        let mut receiver2 = sender.get_new_receiver_with_latest();
+        assert_eq!(receiver2.next().now_or_never(), Some(Some(2)));
+        assert!(receiver2.next().now_or_never().is_none());
+
+        let mut receiver3 = sender.get_new_receiver();
+        assert!(receiver3.next().now_or_never().is_none());
+
+        // Later statuses are received as usual
+        sender.send_status(3);
+        assert_eq!(receiver2.next().now_or_never(), Some(Some(3)));
+        assert_eq!(receiver3.next().now_or_never(), Some(Some(3)));
+    }
 }
@@ -1,7 +1,11 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use futures::lock::Mutex;
 
-use crate::{trigger::Source, Device, DeviceError};
+use crate::{
+    scpi::format::{format_bool, format_int, format_list},
+    trigger::Source,
+    Device, DeviceError, ExecuteOutput,
+};
 
 /// A device that echoes any command sent to it.
 #[derive(Clone)]
@@ -14,8 +18,8 @@ impl EchoDevice {
 }
 
 impl Device for EchoDevice {
-    fn execute(&mut self, cmd: &[u8]) -> Option<Vec<u8>> {
-        Some(cmd.to_vec())
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        Some(ExecuteOutput::Borrowed(cmd))
     }
 
     fn get_status(&mut self) -> Result<u8, DeviceError> {
@@ -35,7 +39,7 @@ impl Device for EchoDevice {
     }
 }
 
-/// A device with some simple commands like `*IDN?`, `EVENT` and `QUERY?`.
+/// A device with some simple commands like `*IDN?`, `*OPC?`, `*STB?`, `EVENT`, `QUERY?` and `DATA?`.
 /// Useful for debugging
 #[derive(Clone)]
 pub struct SimpleDevice {
@@ -65,7 +69,7 @@ impl SimpleDevice {
 }
 
 impl Device for SimpleDevice {
-    fn execute(&mut self, cmd: &[u8]) -> Option<Vec<u8>> {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
         log::debug!(">>> {:?}", cmd);
         let r = match cmd {
             x if x.eq_ignore_ascii_case(b"*IDN?") || x.eq_ignore_ascii_case(b"*IDN?\n") => {
@@ -75,6 +79,16 @@ impl Device for SimpleDevice {
             x if x.eq_ignore_ascii_case(b"QUERY?") || x.eq_ignore_ascii_case(b"QUERY?\n") => {
                 Some(b"RESPONSE".to_vec())
             }
+            x if x.eq_ignore_ascii_case(b"*OPC?") || x.eq_ignore_ascii_case(b"*OPC?\n") => {
+                Some(format_bool(true).into_bytes())
+            }
+            x if x.eq_ignore_ascii_case(b"*STB?") || x.eq_ignore_ascii_case(b"*STB?\n") => {
+                let stb = self.get_status().unwrap_or(0);
+                Some(format_int(stb as i64).into_bytes())
+            }
+            x if x.eq_ignore_ascii_case(b"DATA?") || x.eq_ignore_ascii_case(b"DATA?\n") => {
+                Some(format_list(&[1.0, -0.25, 1.5e-3, f64::INFINITY, f64::NAN]).into_bytes())
+            }
             _ => {
                 let mut rev = cmd.to_vec();
                 rev.reverse();
@@ -82,7 +96,7 @@ impl Device for SimpleDevice {
             }
         };
         log::debug!("<<< {:?}", r);
-        r
+        r.map(ExecuteOutput::Owned)
     }
 
     fn get_status(&mut self) -> Result<u8, DeviceError> {
@@ -113,3 +127,195 @@ impl Device for SimpleDevice {
         Ok(())
     }
 }
+
+/// A device implementing the IEEE 488.2 mandated commands `*IDN?`, `*RST`, `*CLS`, `*STB?`,
+/// `*OPC`, `*OPC?`, `*ESR?`, `*ESE`, `*ESE?`, `*SRE` and `*SRE?`.
+///
+/// Multiple commands may be separated by `;`. Any other command sets the command error bit in the
+/// standard event status register.
+#[derive(Clone)]
+pub struct ScpiDevice {
+    idn: &'static [u8],
+    /// Standard event status register
+    esr: u8,
+    /// Standard event status enable register
+    ese: u8,
+    /// Service request enable register
+    sre: u8,
+    rmt: bool,
+}
+
+impl ScpiDevice {
+    /// Operation complete
+    pub const ESR_OPC: u8 = 0x01;
+    /// Command error
+    pub const ESR_CME: u8 = 0x20;
+
+    /// Event status bit
+    pub const STB_ESB: u8 = 0x20;
+    /// Request service bit
+    pub const STB_RQS: u8 = 0x40;
+
+    /// Create a device responding to `*IDN?` with `idn`
+    pub fn new(idn: &'static [u8]) -> Self {
+        Self {
+            idn,
+            esr: 0,
+            ese: 0,
+            sre: 0,
+            rmt: false,
+        }
+    }
+
+    pub fn new_arc(idn: &'static [u8]) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self::new(idn)))
+    }
+
+    /// Current status byte
+    pub fn stb(&self) -> u8 {
+        let mut stb = 0;
+        if self.esr & self.ese != 0 {
+            stb |= Self::STB_ESB;
+        }
+        if stb & self.sre & !Self::STB_RQS != 0 {
+            stb |= Self::STB_RQS;
+        }
+        stb
+    }
+
+    /// Execute a single command, returns a response if it was a query
+    fn execute_one(&mut self, cmd: &[u8]) -> Option<String> {
+        let (header, arg) = match cmd.iter().position(u8::is_ascii_whitespace) {
+            Some(i) => (&cmd[..i], cmd[i..].trim_ascii()),
+            None => (cmd, &cmd[..0]),
+        };
+        let arg = core::str::from_utf8(arg)
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok());
+
+        match (header.to_ascii_uppercase().as_slice(), arg) {
+            (b"*IDN?", None) => Some(String::from_utf8_lossy(self.idn).into_owned()),
+            (b"*RST", None) => None,
+            (b"*CLS", None) => {
+                self.esr = 0;
+                None
+            }
+            (b"*STB?", None) => Some(format_int(self.stb() as i64)),
+            (b"*OPC", None) => {
+                self.esr |= Self::ESR_OPC;
+                None
+            }
+            (b"*OPC?", None) => Some(format_bool(true)),
+            (b"*ESR?", None) => {
+                let esr = self.esr;
+                self.esr = 0;
+                Some(format_int(esr as i64))
+            }
+            (b"*ESE", Some(ese)) => {
+                self.ese = ese;
+                None
+            }
+            (b"*ESE?", None) => Some(format_int(self.ese as i64)),
+            (b"*SRE", Some(sre)) => {
+                self.sre = sre & !Self::STB_RQS;
+                None
+            }
+            (b"*SRE?", None) => Some(format_int(self.sre as i64)),
+            _ => {
+                self.esr |= Self::ESR_CME;
+                None
+            }
+        }
+    }
+}
+
+impl Device for ScpiDevice {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        let mut responses: Vec<String> = Vec::new();
+        for cmd in cmd.split(|b| *b == b';') {
+            let cmd = cmd.trim_ascii();
+            if cmd.is_empty() {
+                continue;
+            }
+            if let Some(response) = self.execute_one(cmd) {
+                responses.push(response);
+            }
+        }
+
+        if responses.is_empty() {
+            None
+        } else {
+            Some(ExecuteOutput::Owned(responses.join(";").into_bytes()))
+        }
+    }
+
+    fn get_status(&mut self) -> Result<u8, DeviceError> {
+        Ok(self.stb() | (self.rmt as u8) << 7)
+    }
+
+    fn trigger(&mut self, _source: Source) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn set_remote(&mut self, remote: bool) -> Result<(), DeviceError> {
+        self.rmt = remote;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScpiDevice;
+    use crate::Device;
+
+    fn query(dev: &mut ScpiDevice, cmd: &[u8]) -> Option<alloc::vec::Vec<u8>> {
+        dev.execute(cmd).map(|output| output.into_vec())
+    }
+
+    #[test]
+    fn test_scpi_device() {
+        let mut dev = ScpiDevice::new(b"Vendor,Model,0,1.0");
+
+        assert_eq!(query(&mut dev, b"*IDN?\n").unwrap(), b"Vendor,Model,0,1.0");
+        assert_eq!(query(&mut dev, b"*opc?").unwrap(), b"1");
+        assert_eq!(query(&mut dev, b"*RST"), None);
+        assert_eq!(query(&mut dev, b"*STB?").unwrap(), b"0");
+
+        // Operation complete is reported in ESR, cleared by reading it
+        assert_eq!(query(&mut dev, b"*OPC"), None);
+        assert_eq!(query(&mut dev, b"*ESR?").unwrap(), b"1");
+        assert_eq!(query(&mut dev, b"*ESR?").unwrap(), b"0");
+
+        // Unknown command sets command error, cleared by *CLS
+        assert_eq!(query(&mut dev, b"FOO"), None);
+        assert_eq!(query(&mut dev, b"*CLS;*ESR?").unwrap(), b"0");
+
+        // Missing parameter
+        assert_eq!(query(&mut dev, b"*ESE"), None);
+        assert_eq!(query(&mut dev, b"*ESR?").unwrap(), b"32");
+    }
+
+    #[test]
+    fn test_scpi_device_status() {
+        let mut dev = ScpiDevice::new(b"Vendor,Model,0,1.0");
+
+        assert_eq!(query(&mut dev, b"*ESE 1;*SRE 32"), None);
+        assert_eq!(query(&mut dev, b"*ESE?;*SRE?").unwrap(), b"1;32");
+        assert_eq!(dev.get_status().unwrap(), 0);
+
+        // Event summary and service request
+        assert_eq!(query(&mut dev, b"*OPC"), None);
+        assert_eq!(dev.get_status().unwrap(), 0x60);
+        assert_eq!(query(&mut dev, b"*STB?").unwrap(), b"96");
+
+        assert_eq!(query(&mut dev, b"*CLS"), None);
+        assert_eq!(dev.get_status().unwrap(), 0);
+
+        dev.set_remote(true).unwrap();
+        assert_eq!(dev.get_status().unwrap(), 0x80);
+    }
+}
@@ -14,9 +14,10 @@ use super::Protocol;
 
 pub(crate) mod prelude {
     pub(crate) use super::{
-        AsyncInitializeResponseControl, AsyncInitializeResponseParameter, FeatureBitmap,
-        InitializeParameter, InitializeResponseControl, InitializeResponseParameter, Message,
-        MessageType, ReleaseLockControl, RequestLockControl, RmtDeliveredControl,
+        AsyncInitializeResponseControl, AsyncInitializeResponseParameter,
+        AuthenticationResultControl, FeatureBitmap, InitializeParameter, InitializeResponseControl,
+        InitializeResponseParameter, Message, MessageType, ReleaseLockControl, RequestLockControl,
+        RmtDeliveredControl,
     };
 }
 
@@ -31,6 +32,11 @@ pub(crate) struct Message {
 impl Message {
     pub const MESSAGE_HEADER_SIZE: usize = 16;
 
+    /// Total size of message, including header
+    pub(crate) fn len(&self) -> usize {
+        Self::MESSAGE_HEADER_SIZE + self.payload.len()
+    }
+
     pub(crate) fn with_payload(self, payload: Vec<u8>) -> Self {
         Self { payload, ..self }
     }
@@ -443,18 +449,27 @@ impl Display for FeatureBitmap {
 pub(crate) enum RequestLockControl {
     Failure = 0,
     Success = 1,
-    Error = 2,
+    Error = 3,
 }
 
 impl From<SharedLockError> for RequestLockControl {
     fn from(err: SharedLockError) -> Self {
         match err {
-            SharedLockError::Timeout => RequestLockControl::Failure,
+            // Lock held by someone else could not be granted in time
+            SharedLockError::Timeout
+            | SharedLockError::LockedByShared
+            | SharedLockError::LockedByExclusive => RequestLockControl::Failure,
             _ => RequestLockControl::Error,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum AuthenticationResultControl {
+    Failure = 0,
+    Success = 1,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum ReleaseLockControl {
     SuccessExclusive = 1,
@@ -0,0 +1,70 @@
+//! SASL authentication of clients (HiSLIP 2.0)
+
+use std::fmt::Debug;
+use std::str::from_utf8;
+
+/// Credentials accepted by a server.
+pub trait HislipAuthStorage: Debug + Send + Sync {
+    /// Check if user `authcid` with `password` may log in, acting as `authzid` if not empty.
+    ///
+    /// Secrets should be compared using [ct_eq] to avoid leaking them through response times.
+    fn authenticate(&self, authzid: &str, authcid: &str, password: &str) -> bool;
+}
+
+/// Space separated list of supported SASL mechanisms
+pub(crate) const MECHANISMS: &[u8] = b"PLAIN";
+
+/// Compare two strings in constant time, i.e. the time taken does not depend on where they differ.
+///
+/// The time taken still depends on the length of `b`.
+pub fn ct_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut diff = (a.len() != b.len()) as u8;
+    for (i, y) in b.iter().enumerate() {
+        let x = a.get(i).copied().unwrap_or(!*y);
+        diff |= x ^ y;
+    }
+    core::hint::black_box(diff) == 0
+}
+
+/// Parse a SASL PLAIN message, `[authzid] NUL authcid NUL passwd` (RFC 4616).
+pub(crate) fn parse_plain(data: &[u8]) -> Option<(&str, &str, &str)> {
+    let mut fields = data.split(|b| *b == 0);
+    let authzid = from_utf8(fields.next()?).ok()?;
+    let authcid = from_utf8(fields.next()?).ok()?;
+    let password = from_utf8(fields.next()?).ok()?;
+    if fields.next().is_some() || authcid.is_empty() {
+        return None;
+    }
+    Some((authzid, authcid, password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ct_eq, parse_plain};
+
+    #[test]
+    fn test_parse_plain() {
+        assert_eq!(
+            parse_plain(b"admin\0user\0secret"),
+            Some(("admin", "user", "secret"))
+        );
+        assert_eq!(parse_plain(b"\0user\0"), Some(("", "user", "")));
+        assert_eq!(parse_plain(b"\0\0secret"), None);
+        assert_eq!(parse_plain(b"user\0secret"), None);
+        assert_eq!(parse_plain(b"\0user\0secret\0"), None);
+        assert_eq!(parse_plain(b"\0user\0\xff"), None);
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq("secret", "secret"));
+        assert!(ct_eq("", ""));
+        assert!(!ct_eq("secret", "secreT"));
+        assert!(!ct_eq("Secret", "secret"));
+        assert!(!ct_eq("secret", "secret2"));
+        assert!(!ct_eq("secret2", "secret"));
+        assert!(!ct_eq("", "secret"));
+        assert!(!ct_eq("secret", ""));
+    }
+}
@@ -1,25 +1,38 @@
 use std::cmp::min;
 use std::collections::HashMap;
 use std::io;
+use std::net::SocketAddr;
 use std::str::from_utf8;
 use std::sync::Weak;
+use std::time::Duration;
 
+use async_listen::ListenExt;
 use async_std::net::{TcpListener, ToSocketAddrs};
 use async_std::sync::Arc;
 
+use futures::future::{self, Either};
 use futures::task::{Spawn, SpawnExt};
-use futures::{AsyncRead, AsyncWrite, AsyncWriteExt, Stream, StreamExt};
+use futures::{
+    pin_mut, AsyncRead, AsyncWrite, AsyncWriteExt, Future, FutureExt, Stream, StreamExt,
+};
+#[cfg(feature = "fault-injection")]
+use lxi_device::fault::FaultInjection;
 use lxi_device::lock::{LockHandle, Mutex, RemoteLockHandle, SharedLock, SpinMutex};
+use lxi_device::stats::MessageStats;
 use lxi_device::status::Sender as StatusSender;
-use lxi_device::Device;
+use lxi_device::{AsyncDevice, Device};
 
 use crate::common::errors::{Error, FatalErrorCode, NonFatalErrorCode};
 use crate::common::messages::{prelude::*, send_fatal, send_nonfatal};
 use crate::common::{Protocol, SUPPORTED_PROTOCOL};
+use crate::server::auth::HislipAuthStorage;
 use crate::server::session::{SessionState, SharedSession};
+use crate::server::stats::StatsStream;
 use crate::DEFAULT_DEVICE_SUBADRESS;
 
+pub mod auth;
 pub mod session;
+mod stats;
 
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
@@ -30,9 +43,36 @@ pub struct ServerConfig {
     pub prefer_overlap: bool,
     /// Maximum allowed number of sessions
     pub max_num_sessions: usize,
+    /// Maximum number of connections served at once by each listening address.
+    /// A session uses two connections. New connections wait to be accepted while at the limit.
+    pub max_connections: usize,
     /// Short circuited "*IDN?" response.
     /// This should be set identical to what a real "*IDN?" command would return.
     pub short_idn: Option<Vec<u8>>,
+    /// Maximum time a synchronous channel waits for the device before giving up on a message.
+    /// Waits forever (or until a device clear) if `None`.
+    pub lock_timeout: Option<Duration>,
+    /// Time a shared lock, and any exclusive lock held with it, is kept after both channels of its session
+    /// have closed, allowing a reconnecting client using the same lock string to reclaim them.
+    /// Released immediately if `None`.
+    pub lock_grace: Option<Duration>,
+    /// Faults injected into synchronous channel responses. Only intended for testing clients.
+    #[cfg(feature = "fault-injection")]
+    pub faults: FaultInjection,
+    /// Abandon a response and send `Interrupted` when the client sends a new command before the
+    /// response has been completely sent. Only done in overlapped mode, a synchronized session
+    /// always sends complete responses.
+    pub allow_interrupt: bool,
+    /// Descriptor bits advertised in a HiSLIP 2.0 GetDescriptorsResponse.
+    pub descriptors: u32,
+    /// Maximum time to wait for a message from the client before closing the connection.
+    /// Should be longer than any expected idle time of a client. Waits forever if `None`.
+    pub io_timeout: Option<Duration>,
+    /// Credentials clients must authenticate with (SASL PLAIN) before sending any commands.
+    /// Only available to HiSLIP 2.0 clients, authentication is not required if `None`.
+    pub auth: Option<Arc<dyn HislipAuthStorage>>,
+    /// Message size statistics (header included) of all sessions, not collected if `None`.
+    pub stats: Option<Arc<MessageStats>>,
 }
 
 impl ServerConfig {
@@ -56,6 +96,11 @@ impl ServerConfig {
         self
     }
 
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
     pub fn prefer_overlap(mut self) -> Self {
         self.prefer_overlap = true;
         self
@@ -65,6 +110,47 @@ impl ServerConfig {
         self.prefer_overlap = false;
         self
     }
+
+    pub fn lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = Some(lock_timeout);
+        self
+    }
+
+    pub fn lock_grace(mut self, lock_grace: Duration) -> Self {
+        self.lock_grace = Some(lock_grace);
+        self
+    }
+
+    pub fn io_timeout(mut self, io_timeout: Duration) -> Self {
+        self.io_timeout = Some(io_timeout);
+        self
+    }
+
+    pub fn allow_interrupt(mut self, allow_interrupt: bool) -> Self {
+        self.allow_interrupt = allow_interrupt;
+        self
+    }
+
+    pub fn descriptors(mut self, descriptors: u32) -> Self {
+        self.descriptors = descriptors;
+        self
+    }
+
+    pub fn auth(mut self, auth: Arc<dyn HislipAuthStorage>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub fn stats(mut self, stats: Arc<MessageStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injection(mut self, faults: FaultInjection) -> Self {
+        self.faults = faults;
+        self
+    }
 }
 
 impl Default for ServerConfig {
@@ -74,16 +160,40 @@ impl Default for ServerConfig {
             max_message_size: 1024 * 1024,
             prefer_overlap: true,
             max_num_sessions: 64,
+            max_connections: 128,
             short_idn: None,
+            lock_timeout: None,
+            lock_grace: None,
+            #[cfg(feature = "fault-injection")]
+            faults: FaultInjection::default(),
+            io_timeout: None,
+            allow_interrupt: false,
+            descriptors: 0,
+            auth: None,
+            stats: None,
         }
     }
 }
 
+/// Await `fut`, returns `None` if `timeout` expires first.
+pub(crate) async fn with_io_timeout<F>(timeout: Option<Duration>, fut: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    match timeout {
+        Some(t) => async_std::future::timeout(t, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
 type DeviceMap<DEV> = HashMap<String, (Arc<SpinMutex<SharedLock>>, Arc<Mutex<DEV>>)>;
 
+type StatusMap = HashMap<String, StatusSender>;
+
 pub struct ServerBuilder<DEV> {
     config: ServerConfig,
     devices: DeviceMap<DEV>,
+    statuses: StatusMap,
 }
 
 impl<DEV> Default for ServerBuilder<DEV> {
@@ -91,18 +201,20 @@ impl<DEV> Default for ServerBuilder<DEV> {
         Self {
             config: Default::default(),
             devices: HashMap::new(),
+            statuses: HashMap::new(),
         }
     }
 }
 
 impl<DEV> ServerBuilder<DEV>
 where
-    DEV: Device + Send + 'static,
+    DEV: AsyncDevice,
 {
     pub fn new(config: ServerConfig) -> Self {
         Self {
             config,
             devices: HashMap::new(),
+            statuses: HashMap::new(),
         }
     }
 
@@ -125,12 +237,45 @@ where
         self
     }
 
+    /// Send service requests from `status` only to sessions of device `subaddr`.
+    ///
+    /// Sessions of devices without a status sender receive service requests from the sender passed to [Server::accept].
+    pub fn device_status(mut self, subaddr: String, status: StatusSender) -> Self {
+        self.statuses.insert(subaddr, status);
+        self
+    }
+
+    pub fn vendor_id(mut self, vendor_id: u16) -> Self {
+        self.config.vendor_id = vendor_id;
+        self
+    }
+
+    pub fn max_message_size(mut self, max_message_size: u64) -> Self {
+        self.config.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn max_num_sessions(mut self, max_num_sessions: usize) -> Self {
+        self.config.max_num_sessions = max_num_sessions;
+        self
+    }
+
+    pub fn prefer_overlap(mut self) -> Self {
+        self.config.prefer_overlap = true;
+        self
+    }
+
+    pub fn prefer_synchronized(mut self) -> Self {
+        self.config.prefer_overlap = false;
+        self
+    }
+
     pub fn build(self) -> Arc<Server<DEV>> {
         assert!(
             !self.devices.is_empty(),
             "Server must have one or more devices"
         );
-        Server::with_config(self.config, self.devices)
+        Server::with_statuses(self.config, self.devices, self.statuses)
     }
 }
 
@@ -140,12 +285,13 @@ where
 {
     inner: Arc<Mutex<InnerServer<DEV>>>,
     devices: DeviceMap<DEV>,
+    statuses: StatusMap,
     config: ServerConfig,
 }
 
 impl<DEV> Server<DEV>
 where
-    DEV: Device + Send + 'static,
+    DEV: AsyncDevice,
 {
     pub fn new(devices: DeviceMap<DEV>) -> Arc<Self> {
         let config = ServerConfig::default();
@@ -153,54 +299,181 @@ where
     }
 
     pub fn with_config(config: ServerConfig, devices: DeviceMap<DEV>) -> Arc<Self> {
+        Self::with_statuses(config, devices, HashMap::new())
+    }
+
+    fn with_statuses(
+        config: ServerConfig,
+        devices: DeviceMap<DEV>,
+        statuses: StatusMap,
+    ) -> Arc<Self> {
         Arc::new(Server {
             inner: InnerServer::new(config.max_num_sessions),
             config,
             devices,
+            statuses,
         })
     }
 
     /// Start accepting connections from addr
     ///
     pub async fn accept<P>(
+        self: Arc<Self>,
+        addr: impl ToSocketAddrs,
+        srq: StatusSender,
+        spawner: P,
+    ) -> Result<(), io::Error>
+    where
+        P: Spawn,
+    {
+        self.accept_until(addr, srq, spawner, future::pending())
+            .await
+    }
+
+    /// Start accepting connections from addr until `shutdown` completes.
+    ///
+    /// The listener is closed on shutdown, already connected sessions keep running until closed by their client.
+    pub async fn accept_until<P, F>(
         self: Arc<Self>,
         addr: impl ToSocketAddrs,
         mut srq: StatusSender,
         spawner: P,
+        shutdown: F,
     ) -> Result<(), io::Error>
     where
         P: Spawn,
+        F: Future<Output = ()>,
     {
         let listener = TcpListener::bind(addr).await?;
-        let mut incoming = listener.incoming();
-        while let Some(stream) = incoming.next().await {
-            let stream = stream?;
-            let peer = stream.peer_addr()?;
+        let mut incoming = listener
+            .incoming()
+            .log_warnings(|warn| log::warn!("Listening error: {}", warn))
+            .handle_errors(Duration::from_millis(100))
+            .backpressure(self.config.max_connections);
+        pin_mut!(shutdown);
+        loop {
+            let (token, stream) = match future::select(incoming.next(), &mut shutdown).await {
+                Either::Left((Some(accepted), _)) => accepted,
+                Either::Left((None, _)) | Either::Right(_) => break,
+            };
+            // Client may already have disconnected, skip it and keep accepting
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(err) => {
+                    log::warn!("Failed to get peer address: {}", err);
+                    continue;
+                }
+            };
 
             let s = self.clone();
-            let t = srq.get_new_receiver();
+            let t = srq.get_new_receiver_with_latest();
             let _res = spawner.spawn(async move {
                 log::info!("{peer} connected");
                 let res = s.handle_session(peer.to_string(), stream, t).await;
 
-                log::info!("{peer} disconnected: {res:?}")
+                log::info!("{peer} disconnected: {res:?}");
+                drop(token);
             });
         }
+        log::info!("Stopped accepting connections");
         Ok(())
     }
 
+    /// Start accepting connections from multiple addresses, e.g. both `0.0.0.0:4880` and `[::]:4880`.
+    ///
+    /// Sessions are shared between all addresses.
+    pub async fn accept_multi<P>(
+        self: Arc<Self>,
+        addrs: Vec<SocketAddr>,
+        srq: StatusSender,
+        spawner: P,
+    ) -> Result<(), io::Error>
+    where
+        P: Spawn,
+    {
+        self.accept_multi_until(addrs, srq, spawner, future::pending())
+            .await
+    }
+
+    /// Start accepting connections from multiple addresses until `shutdown` completes.
+    ///
+    /// All listeners are closed on shutdown, already connected sessions keep running until closed by their client.
+    pub async fn accept_multi_until<P, F>(
+        self: Arc<Self>,
+        addrs: Vec<SocketAddr>,
+        srq: StatusSender,
+        spawner: P,
+        shutdown: F,
+    ) -> Result<(), io::Error>
+    where
+        P: Spawn,
+        F: Future<Output = ()>,
+    {
+        let shutdown = shutdown.shared();
+        let listeners = addrs.into_iter().map(|addr| {
+            self.clone()
+                .accept_until(addr, srq.clone(), &spawner, shutdown.clone())
+        });
+        future::try_join_all(listeners).await?;
+        Ok(())
+    }
+
+    /// Keep the locks of a closed session for a while in case the client reconnects.
+    /// Only done by the last channel of the session to close, the grace period runs in a separate task.
+    fn retain_locks(&self, device: Arc<SpinMutex<LockHandle<DEV>>>, peer: &str, id: u16) {
+        let grace = match self.config.lock_grace {
+            Some(grace) => grace,
+            None => return,
+        };
+        // The other channel is still using the locks
+        let mut handle = match Arc::into_inner(device) {
+            Some(handle) => handle.into_inner(),
+            None => return,
+        };
+        if let Some(token) = handle.retain() {
+            log::debug!(peer=peer, session_id=id; "Retaining locks for {grace:?}");
+            let lock = handle.shared_lock();
+            let peer = peer.to_string();
+            async_std::task::spawn(async move {
+                async_std::task::sleep(grace).await;
+                if lock.lock().release_retained(token) {
+                    log::debug!(peer=peer, session_id=id; "Released retained locks");
+                }
+            });
+        }
+    }
+
     async fn handle_session<S, SRQ>(
         &self,
         peer: String,
-        mut stream: S,
+        stream: S,
         srq: SRQ,
     ) -> Result<(), io::Error>
     where
         S: AsyncRead + AsyncWrite + Unpin,
         SRQ: Stream<Item = u8> + Unpin,
     {
+        let mut stream = StatsStream::new(stream, self.config.stats.clone());
         loop {
-            match Message::read_from(&mut stream, self.config.max_message_size).await? {
+            let msg = with_io_timeout(
+                self.config.io_timeout,
+                Message::read_from(&mut stream, self.config.max_message_size),
+            )
+            .await;
+            let msg = match msg {
+                Some(msg) => msg?,
+                None => {
+                    send_nonfatal!(peer=peer.to_string();
+                        &mut stream, NonFatalErrorCode::UnidentifiedError,
+                        "Timed out waiting for initialization"
+                    );
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            };
+            if let (Some(stats), Ok(msg)) = (&self.config.stats, &msg) {
+                stats.inbound.record(msg.len());
+            }
+            match msg {
                 Ok(msg) => {
                     log::trace!("Received {:?}", msg);
                     match msg {
@@ -282,8 +555,15 @@ where
                                             );
 
                                             let receiver = {
-                                                let s = shared.lock().await;
-                                                s.get_clear_receiver()
+                                                let mut shared = shared.lock().await;
+                                                if let Some(status) = self.statuses.get(&s) {
+                                                    shared.set_srq_receiver(
+                                                        status
+                                                            .clone()
+                                                            .get_new_receiver_with_latest(),
+                                                    );
+                                                }
+                                                shared.get_clear_receiver()
                                             };
 
                                             // Send response
@@ -299,12 +579,13 @@ where
                                                 id,
                                                 self.config.clone(),
                                                 shared,
-                                                RemoteLockHandle::new(device),
+                                                RemoteLockHandle::new(device.clone()),
                                                 receiver,
                                             )
                                             .handle_session(stream, peer.clone(), protocol)
                                             .await;
                                             log::debug!(peer=peer.to_string(), session_id=id; "Sync session closed: {res:?}");
+                                            self.retain_locks(device, &peer, id);
                                             return res;
                                         }
                                         Err(err) => {
@@ -357,6 +638,10 @@ where
                                 session_guard.set_state(SessionState::Normal);
                                 let protocol = session_guard.protocol();
                                 let sender = session_guard.get_clear_sender();
+                                let srq = match session_guard.take_srq_receiver() {
+                                    Some(device_srq) => Either::Right(device_srq),
+                                    None => Either::Left(srq),
+                                };
                                 drop(session_guard);
 
                                 MessageType::AsyncInitializeResponse
@@ -376,12 +661,15 @@ where
                                     id,
                                     self.config.clone(),
                                     shared,
-                                    device,
+                                    device.clone(),
                                     sender,
                                 )
                                 .handle_session(stream, peer.clone(), srq, protocol)
                                 .await;
                                 log::debug!(peer=peer.to_string(), session_id=id; "Async session closed: {res:?}");
+
+                                drop(session);
+                                self.retain_locks(device, &peer, id);
                                 return res;
                             }
                         }
@@ -529,3 +817,1436 @@ where
         self.sessions.retain(|_, session| session.active())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::sync::Arc;
+    use async_std::task;
+    use byteorder::{ByteOrder, NetworkEndian};
+    use futures::task::{FutureObj, Spawn, SpawnError};
+    use futures::StreamExt;
+    use lxi_device::lock::{LockHandle, Mutex, SharedLock};
+    use lxi_device::stats::MessageStats;
+    use lxi_device::status::Sender as StatusSender;
+    use lxi_device::trigger::Source;
+    use lxi_device::util::EchoDevice;
+    use lxi_device::{AsyncDevice, Device, DeviceError, ExecuteOutput};
+
+    use super::auth::ct_eq;
+    use super::{HislipAuthStorage, InnerServer, Server, ServerBuilder, ServerConfig};
+    use crate::common::errors::{Error, FatalErrorCode};
+    use crate::common::messages::prelude::*;
+    use crate::common::SUPPORTED_PROTOCOL;
+    use crate::DEFAULT_DEVICE_SUBADRESS;
+
+    struct TaskSpawner;
+
+    impl Spawn for TaskSpawner {
+        fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+            task::spawn(future);
+            Ok(())
+        }
+    }
+
+    /// Serve a single device on an ephemeral port
+    async fn serve<DEV>(config: ServerConfig, device: DEV) -> SocketAddr
+    where
+        DEV: AsyncDevice,
+    {
+        let server = ServerBuilder::new(config)
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                Arc::new(Mutex::new(device)),
+                SharedLock::new(),
+            )
+            .build();
+        listen(server).await
+    }
+
+    /// Serve on an ephemeral port
+    async fn listen<DEV>(server: Arc<Server<DEV>>) -> SocketAddr
+    where
+        DEV: AsyncDevice,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        task::spawn(async move {
+            let mut srq = StatusSender::new();
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let server = server.clone();
+                let srq = srq.get_new_receiver();
+                task::spawn(async move {
+                    let _ = server.handle_session("test".to_string(), stream, srq).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Device reporting a fixed status, status and trigger are not supported if `None`
+    struct StatusDevice(Option<u8>);
+
+    impl Device for StatusDevice {
+        fn execute<'a>(&mut self, _cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            None
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            self.0.ok_or(DeviceError::NotSupported)
+        }
+
+        fn trigger(&mut self, _source: Source) -> Result<(), DeviceError> {
+            self.0.map(|_| ()).ok_or(DeviceError::NotSupported)
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    impl AsyncDevice for StatusDevice {}
+
+    /// Device recording any remote state changes
+    #[derive(Clone, Default)]
+    struct RemoteDevice(Arc<std::sync::Mutex<Vec<bool>>>);
+
+    impl Device for RemoteDevice {
+        fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            Some(ExecuteOutput::Borrowed(cmd))
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            Ok(0)
+        }
+
+        fn trigger(&mut self, _source: Source) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_remote(&mut self, remote: bool) -> Result<(), DeviceError> {
+            self.0.lock().unwrap().push(remote);
+            Ok(())
+        }
+    }
+
+    impl AsyncDevice for RemoteDevice {}
+
+    /// Device recording trigger sources
+    #[derive(Clone, Default)]
+    struct TriggerDevice(Arc<std::sync::Mutex<Vec<Source>>>);
+
+    impl Device for TriggerDevice {
+        fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            Some(ExecuteOutput::Borrowed(cmd))
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            Ok(0)
+        }
+
+        fn trigger(&mut self, source: Source) -> Result<(), DeviceError> {
+            self.0.lock().unwrap().push(source);
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    impl AsyncDevice for TriggerDevice {}
+
+    /// Device responding with `chunks` chunks of 4 bytes, declaring a total length of `len`
+    struct ChunkDevice {
+        chunks: usize,
+        len: usize,
+    }
+
+    impl Device for ChunkDevice {
+        fn execute<'a>(&mut self, _cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            let chunks = self.chunks;
+            Some(ExecuteOutput::Stream {
+                len: self.len,
+                chunks: Box::new((0..chunks).map(|i| format!("{i:03},").into_bytes())),
+            })
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            Ok(0)
+        }
+
+        fn trigger(&mut self, _source: Source) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    impl AsyncDevice for ChunkDevice {}
+
+    #[derive(Debug)]
+    struct TestAuth;
+
+    impl HislipAuthStorage for TestAuth {
+        fn authenticate(&self, _authzid: &str, authcid: &str, password: &str) -> bool {
+            authcid == "user" && ct_eq(password, "secret")
+        }
+    }
+
+    async fn recv(stream: &mut TcpStream) -> Message {
+        Message::read_from(stream, u64::MAX).await.unwrap().unwrap()
+    }
+
+    /// Open a synchronous channel, returns the channel and session id
+    async fn initialize(addr: SocketAddr) -> (TcpStream, u32) {
+        initialize_subaddr(addr, DEFAULT_DEVICE_SUBADRESS).await
+    }
+
+    /// Open a synchronous channel to device `subaddr`, returns the channel and session id
+    async fn initialize_subaddr(addr: SocketAddr, subaddr: &str) -> (TcpStream, u32) {
+        let mut sync = TcpStream::connect(addr).await.unwrap();
+        MessageType::Initialize
+            .message_params(0, SUPPORTED_PROTOCOL.as_parameter(0))
+            .with_payload(subaddr.as_bytes().to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::InitializeResponse);
+        (sync, resp.message_parameter & 0xFFFF)
+    }
+
+    /// Open an asynchronous channel for a session, returns the channel and response
+    async fn async_initialize(addr: SocketAddr, session_id: u32) -> (TcpStream, Message) {
+        let mut asynchronous = TcpStream::connect(addr).await.unwrap();
+        MessageType::AsyncInitialize
+            .message_params(0, session_id)
+            .no_payload()
+            .write_to(&mut asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(&mut asynchronous).await;
+        (asynchronous, resp)
+    }
+
+    /// Open a session, returns the synchronous and asynchronous channel
+    async fn connect(addr: SocketAddr) -> (TcpStream, TcpStream) {
+        let (sync, session_id) = initialize(addr).await;
+        let (asynchronous, resp) = async_initialize(addr, session_id).await;
+        assert_eq!(resp.message_type, MessageType::AsyncInitializeResponse);
+        (sync, asynchronous)
+    }
+
+    #[async_std::test]
+    async fn test_max_message_size() {
+        let addr = serve(ServerConfig::default(), EchoDevice).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+
+        // Negotiate a small message size
+        max_message_size(&mut asynchronous, 64).await;
+
+        // Response is split into multiple messages
+        let cmd: Vec<u8> = (0..4096).map(|x| x as u8).collect();
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(cmd.clone())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+
+        let mut data = Vec::new();
+        let mut messages = 0;
+        loop {
+            let msg = recv(&mut sync).await;
+            assert!(msg.payload.len() <= 64);
+            assert_eq!(msg.message_parameter, 0xffffff00);
+            data.extend_from_slice(&msg.payload);
+            messages += 1;
+            match msg.message_type {
+                MessageType::Data => continue,
+                MessageType::DataEnd => break,
+                typ => panic!("Unexpected message {typ:?}"),
+            }
+        }
+        assert_eq!(messages, 4096 / 64);
+        assert_eq!(data, cmd);
+    }
+
+    /// Read a response up to and including DataEnd, returns the messages received
+    async fn read_response(sync: &mut TcpStream, message_id: u32) -> Vec<Message> {
+        let mut messages = Vec::new();
+        loop {
+            let msg = async_std::future::timeout(Duration::from_secs(1), recv(sync))
+                .await
+                .expect("response did not end");
+            assert_eq!(msg.message_parameter, message_id);
+            let end = match msg.message_type {
+                MessageType::Data => false,
+                MessageType::DataEnd => true,
+                typ => panic!("Unexpected message {typ:?}"),
+            };
+            messages.push(msg);
+            if end {
+                return messages;
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn test_stream_chunks() {
+        let device = ChunkDevice {
+            chunks: 1000,
+            len: 1000 * 4,
+        };
+        let addr = serve(ServerConfig::default(), device).await;
+        let (mut sync, _asynchronous) = connect(addr).await;
+
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"DATA?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let messages = read_response(&mut sync, 0xffffff00).await;
+
+        // One message per chunk, only the last one ends the response
+        assert_eq!(messages.len(), 1000);
+        for (i, msg) in messages.iter().enumerate() {
+            assert_eq!(msg.payload, format!("{i:03},").into_bytes());
+        }
+        assert!(messages[..999]
+            .iter()
+            .all(|msg| msg.message_type == MessageType::Data));
+    }
+
+    #[async_std::test]
+    async fn test_stream_wrong_len() {
+        let expected: Vec<u8> = (0..10)
+            .flat_map(|i| format!("{i:03},").into_bytes())
+            .collect();
+
+        // Declared length too small and too large
+        for len in [4, 1000] {
+            let addr = serve(ServerConfig::default(), ChunkDevice { chunks: 10, len }).await;
+            let (mut sync, _asynchronous) = connect(addr).await;
+
+            for message_id in [0xffffff00, 0xffffff02] {
+                MessageType::DataEnd
+                    .message_params(0, message_id)
+                    .with_payload(b"DATA?".to_vec())
+                    .write_to(&mut sync)
+                    .await
+                    .unwrap();
+                let messages = read_response(&mut sync, message_id).await;
+                let data: Vec<u8> = messages.iter().flat_map(|m| m.payload.clone()).collect();
+                assert_eq!(data, expected);
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn test_device_clear() {
+        let addr = serve(ServerConfig::default(), EchoDevice).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+
+        // Partial command
+        MessageType::Data
+            .message_params(0, 0xffffff00)
+            .with_payload(b"ABC".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+
+        // Clear device
+        MessageType::AsyncDeviceClear
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(&mut asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::AsyncDeviceClearAcknowledge);
+
+        MessageType::DeviceClearComplete
+            .message_params(resp.control_code, 0)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DeviceClearAcknowledge);
+
+        // Partial command was discarded
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"XYZ".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(resp.payload, b"XYZ");
+    }
+
+    #[async_std::test]
+    async fn test_device_clear_locked() {
+        let addr = serve(ServerConfig::default(), EchoDevice).await;
+        let (_sync1, mut async1) = connect(addr).await;
+        let (mut sync2, mut async2) = connect(addr).await;
+
+        let resp = async_lock(&mut async1, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+
+        // Command waits for the lock
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"ABC".to_vec())
+            .write_to(&mut sync2)
+            .await
+            .unwrap();
+
+        // Clear device aborts the wait
+        MessageType::AsyncDeviceClear
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut async2)
+            .await
+            .unwrap();
+        let resp = recv(&mut async2).await;
+        assert_eq!(resp.message_type, MessageType::AsyncDeviceClearAcknowledge);
+
+        MessageType::DeviceClearComplete
+            .message_params(resp.control_code, 0)
+            .no_payload()
+            .write_to(&mut sync2)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync2).await;
+        assert_eq!(resp.message_type, MessageType::DeviceClearAcknowledge);
+
+        // Release lock
+        let resp = async_release(&mut async1).await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+
+        // Aborted command was discarded
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"XYZ".to_vec())
+            .write_to(&mut sync2)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync2).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(resp.payload, b"XYZ");
+    }
+
+    #[async_std::test]
+    async fn test_lock_grace() {
+        let config = ServerConfig::default().lock_grace(Duration::from_secs(10));
+        let addr = serve(config, EchoDevice).await;
+
+        // Client holds shared lock "foo" upgraded to exclusive and disconnects
+        let (sync1, mut async1) = connect(addr).await;
+        let resp = async_lock(&mut async1, b"foo").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_lock(&mut async1, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        drop(sync1);
+        drop(async1);
+        task::sleep(Duration::from_millis(100)).await;
+
+        // Locks are kept during the grace period
+        let (_sync2, mut async2) = connect(addr).await;
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+
+        // Client reconnects and reclaims both locks
+        let (mut sync3, mut async3) = connect(addr).await;
+        let resp = async_lock(&mut async3, b"foo").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_lock(&mut async3, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync3)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync3).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+
+        let resp = async_release(&mut async3).await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+        assert_eq!(
+            resp.control_code,
+            ReleaseLockControl::SuccessExclusive as u8
+        );
+    }
+
+    #[async_std::test]
+    async fn test_lock_grace_open_sync() {
+        let config = ServerConfig::default()
+            .lock_grace(Duration::from_secs(10))
+            .lock_timeout(Duration::from_secs(1));
+        let addr = serve(config, EchoDevice).await;
+
+        // Async channel closes while the sync channel is still open
+        let (mut sync1, mut async1) = connect(addr).await;
+        let resp = async_lock(&mut async1, b"foo").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_lock(&mut async1, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        drop(async1);
+        task::sleep(Duration::from_millis(100)).await;
+
+        // Sync channel keeps its lock
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync1)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync1).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+
+        let (_sync2, mut async2) = connect(addr).await;
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+
+        // Lock is kept once the sync channel closes too
+        drop(sync1);
+        task::sleep(Duration::from_millis(100)).await;
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+    }
+
+    #[async_std::test]
+    async fn test_lock_timeout() {
+        let config = ServerConfig::default().lock_timeout(Duration::from_millis(100));
+        let addr = serve(config, EchoDevice).await;
+        let (_sync1, mut async1) = connect(addr).await;
+        let (mut sync2, _async2) = connect(addr).await;
+
+        // Partial command
+        MessageType::Data
+            .message_params(0, 0xffffff00)
+            .with_payload(b"ABC".to_vec())
+            .write_to(&mut sync2)
+            .await
+            .unwrap();
+
+        let resp = async_lock(&mut async1, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+
+        // Rest of command times out waiting for the lock
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"DEF".to_vec())
+            .write_to(&mut sync2)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync2).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+
+        let resp = async_release(&mut async1).await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+
+        // Partial command was discarded
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"XYZ".to_vec())
+            .write_to(&mut sync2)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync2).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(resp.payload, b"XYZ");
+    }
+
+    async fn status_query(asynchronous: &mut TcpStream) -> u8 {
+        MessageType::AsyncStatusQuery
+            .message_params(0, 0xffffff00)
+            .no_payload()
+            .write_to(asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::AsyncStatusResponse);
+        resp.control_code
+    }
+
+    #[async_std::test]
+    async fn test_status_query() {
+        let addr = serve(ServerConfig::default(), StatusDevice(Some(0x44))).await;
+        let (_sync, mut asynchronous) = connect(addr).await;
+        assert_eq!(status_query(&mut asynchronous).await, 0x44);
+
+        // MAV is ignored
+        let addr = serve(ServerConfig::default(), StatusDevice(Some(0x50))).await;
+        let (_sync, mut asynchronous) = connect(addr).await;
+        assert_eq!(status_query(&mut asynchronous).await, 0x40);
+
+        // Unsupported status reported as 0
+        let addr = serve(ServerConfig::default(), StatusDevice(None)).await;
+        let (_sync, mut asynchronous) = connect(addr).await;
+        assert_eq!(status_query(&mut asynchronous).await, 0);
+    }
+
+    async fn remote_local(asynchronous: &mut TcpStream, request: u8) {
+        MessageType::AsyncRemoteLocalControl
+            .message_params(request, 0xffffff00)
+            .no_payload()
+            .write_to(asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::AsyncRemoteLocalResponse);
+    }
+
+    #[async_std::test]
+    async fn test_remote_local() {
+        let device = RemoteDevice::default();
+        let addr = serve(ServerConfig::default(), device.clone()).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+
+        // Enable remote and go to remote
+        remote_local(&mut asynchronous, 3).await;
+        assert_eq!(*device.0.lock().unwrap(), [true]);
+
+        // Disable remote and go to local
+        remote_local(&mut asynchronous, 2).await;
+        assert_eq!(*device.0.lock().unwrap(), [true, false]);
+
+        // Enable remote, device goes to remote on next command
+        remote_local(&mut asynchronous, 1).await;
+        assert_eq!(*device.0.lock().unwrap(), [true, false]);
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(*device.0.lock().unwrap(), [true, false, true]);
+    }
+
+    #[async_std::test]
+    async fn test_trigger() {
+        let device = TriggerDevice::default();
+        let addr = serve(ServerConfig::default(), device.clone()).await;
+        let (mut sync, _asynchronous) = connect(addr).await;
+
+        MessageType::Trigger
+            .message_params(0, 0xffffff00)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+
+        // Wait for a command sent after the trigger
+        MessageType::DataEnd
+            .message_params(0, 0xffffff02)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+
+        assert_eq!(*device.0.lock().unwrap(), [Source::Bus]);
+    }
+
+    #[async_std::test]
+    async fn test_trigger_message_id() {
+        let device = TriggerDevice::default();
+        let addr = serve(ServerConfig::default(), device.clone()).await;
+        let (mut sync, _asynchronous) = connect(addr).await;
+
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+
+        // Reused message id is refused
+        MessageType::Trigger
+            .message_params(0, 0xffffff00)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+        assert!(device.0.lock().unwrap().is_empty());
+
+        // Next message id is accepted
+        MessageType::Trigger
+            .message_params(0, 0xffffff02)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        MessageType::DataEnd
+            .message_params(0, 0xffffff04)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(*device.0.lock().unwrap(), [Source::Bus]);
+    }
+
+    #[async_std::test]
+    async fn test_trigger_first_message_id() {
+        let device = TriggerDevice::default();
+        let addr = serve(ServerConfig::default(), device.clone()).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+
+        // First message must use the initial message id
+        MessageType::Trigger
+            .message_params(0, 0x12345678)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+        assert!(device.0.lock().unwrap().is_empty());
+
+        MessageType::Trigger
+            .message_params(0, 0xffffff00)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+
+        // Message ids restart after a device clear
+        MessageType::AsyncDeviceClear
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(&mut asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::AsyncDeviceClearAcknowledge);
+        MessageType::DeviceClearComplete
+            .message_params(resp.control_code, 0)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DeviceClearAcknowledge);
+
+        MessageType::Trigger
+            .message_params(0, 0xffffff02)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+
+        MessageType::Trigger
+            .message_params(0, 0xffffff00)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        MessageType::DataEnd
+            .message_params(0, 0xffffff02)
+            .with_payload(b"QUERY?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(*device.0.lock().unwrap(), [Source::Bus, Source::Bus]);
+    }
+
+    #[async_std::test]
+    async fn test_trigger_error() {
+        let addr = serve(ServerConfig::default(), StatusDevice(None)).await;
+        let (mut sync, _asynchronous) = connect(addr).await;
+
+        MessageType::Trigger
+            .message_params(0, 0xffffff00)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+    }
+
+    #[async_std::test]
+    async fn test_get_descriptors() {
+        let config = ServerConfig::default().descriptors(0x12345678);
+        let addr = serve(config, EchoDevice).await;
+        let (mut sync, _asynchronous) = connect(addr).await;
+
+        MessageType::GetDescriptors
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::GetDescriptorsResponse);
+        assert_eq!(resp.message_parameter, 0x12345678);
+    }
+
+    #[async_std::test]
+    async fn test_stats() {
+        let stats = Arc::new(MessageStats::default());
+        let config = ServerConfig::default().stats(stats.clone());
+        let addr = serve(config, EchoDevice).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"*IDN?".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+
+        status_query(&mut asynchronous).await;
+
+        MessageType::VendorSpecific(128)
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+
+        // Statistics are recorded right after a message has been written
+        async_std::future::timeout(Duration::from_secs(1), async {
+            while stats.outbound.count() < 5 {
+                task::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // Initialize, AsyncInitialize, DataEnd, AsyncStatusQuery and VendorSpecific
+        assert_eq!(stats.inbound.count(), 5);
+        assert_eq!(stats.inbound.min(), Some(16));
+        assert_eq!(
+            stats.inbound.max(),
+            Some(16 + DEFAULT_DEVICE_SUBADRESS.len())
+        );
+
+        // InitializeResponse, AsyncInitializeResponse, DataEnd, AsyncStatusResponse and Error
+        assert_eq!(stats.outbound.count(), 5);
+        assert_eq!(stats.outbound.min(), Some(16));
+        assert_eq!(stats.outbound.max(), Some(resp.len()));
+    }
+
+    async fn max_message_size(asynchronous: &mut TcpStream, size: u64) {
+        let mut buf = [0u8; 8];
+        NetworkEndian::write_u64(&mut buf, size);
+        MessageType::AsyncMaximumMessageSize
+            .message_params(0, 0)
+            .with_payload(buf.to_vec())
+            .write_to(asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(asynchronous).await;
+        assert_eq!(
+            resp.message_type,
+            MessageType::AsyncMaximumMessageSizeResponse
+        );
+    }
+
+    #[async_std::test]
+    async fn test_interrupted() {
+        let config = ServerConfig::default().allow_interrupt(true);
+        let addr = serve(config, EchoDevice).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+        max_message_size(&mut asynchronous, 16).await;
+
+        // Second command is sent before the first response is read
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(vec![b'A'; 64 * 1024])
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        MessageType::DataEnd
+            .message_params(0, 0xffffff02)
+            .with_payload(b"B".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+
+        // First response is interrupted
+        let mut messages = 0;
+        loop {
+            let msg = recv(&mut sync).await;
+            match msg.message_type {
+                MessageType::Data => {
+                    assert_eq!(msg.message_parameter, 0xffffff00);
+                    messages += 1;
+                }
+                MessageType::Interrupted => {
+                    assert_eq!(msg.message_parameter, 0xffffff00);
+                    break;
+                }
+                typ => panic!("Unexpected message {typ:?}"),
+            }
+        }
+        assert!(messages < 64 * 1024 / 16);
+
+        // Second command is executed
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(resp.message_parameter, 0xffffff02);
+        assert_eq!(resp.payload, b"B");
+    }
+
+    #[async_std::test]
+    async fn test_interrupted_synchronized() {
+        let config = ServerConfig::default().allow_interrupt(true);
+        let addr = serve(config, EchoDevice).await;
+        let (mut sync, mut asynchronous) = connect(addr).await;
+        max_message_size(&mut asynchronous, 16).await;
+
+        // Switch to synchronized mode
+        MessageType::AsyncDeviceClear
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut asynchronous)
+            .await
+            .unwrap();
+        let resp = recv(&mut asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::AsyncDeviceClearAcknowledge);
+        MessageType::DeviceClearComplete
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DeviceClearAcknowledge);
+
+        // Second command is sent before the first response is read
+        MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(vec![b'A'; 64 * 1024])
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        MessageType::DataEnd
+            .message_params(0, 0xffffff02)
+            .with_payload(b"B".to_vec())
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+
+        // First response is sent completely
+        let mut len = 0;
+        loop {
+            let msg = recv(&mut sync).await;
+            assert_eq!(msg.message_parameter, 0xffffff00);
+            len += msg.payload.len();
+            match msg.message_type {
+                MessageType::Data => {}
+                MessageType::DataEnd => break,
+                typ => panic!("Unexpected message {typ:?}"),
+            }
+        }
+        assert_eq!(len, 64 * 1024);
+
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(resp.message_parameter, 0xffffff02);
+        assert_eq!(resp.payload, b"B");
+    }
+
+    #[async_std::test]
+    async fn test_builder_vendor_id() {
+        let server = ServerBuilder::default()
+            .vendor_id(0x1234)
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .build();
+        let addr = listen(server).await;
+
+        let (_sync, session_id) = initialize(addr).await;
+        let (_asynchronous, resp) = async_initialize(addr, session_id).await;
+        assert_eq!(resp.message_type, MessageType::AsyncInitializeResponse);
+        assert_eq!(
+            AsyncInitializeResponseParameter(resp.message_parameter).server_vendor_id(),
+            0x1234
+        );
+    }
+
+    #[async_std::test]
+    async fn test_device_status() {
+        let mut status_a = StatusSender::new();
+        let status_b = StatusSender::new();
+        let server = ServerBuilder::default()
+            .device(
+                "hislip0".to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .device(
+                "hislip1".to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .device_status("hislip0".to_string(), status_a.clone())
+            .device_status("hislip1".to_string(), status_b)
+            .build();
+        let addr = listen(server).await;
+
+        let (_sync_a, session_id) = initialize_subaddr(addr, "hislip0").await;
+        let (mut asynchronous_a, _) = async_initialize(addr, session_id).await;
+        let (_sync_b, session_id) = initialize_subaddr(addr, "hislip1").await;
+        let (mut asynchronous_b, _) = async_initialize(addr, session_id).await;
+
+        // Only sessions of device A receive a service request
+        status_a.send_status(0x40);
+        let resp = recv(&mut asynchronous_a).await;
+        assert_eq!(resp.message_type, MessageType::AsyncServiceRequest);
+        assert_eq!(resp.control_code, 0x40);
+
+        assert_eq!(status_query(&mut asynchronous_b).await, 0);
+    }
+
+    #[async_std::test]
+    async fn test_device_status_latest() {
+        let mut status = StatusSender::new();
+        let server = ServerBuilder::default()
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .device_status(DEFAULT_DEVICE_SUBADRESS.to_string(), status.clone())
+            .build();
+        let addr = listen(server).await;
+
+        // Session connecting after the status was sent still receives the service request
+        status.send_status(0x40);
+        let (_sync, mut asynchronous) = connect(addr).await;
+        let resp = recv(&mut asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::AsyncServiceRequest);
+        assert_eq!(resp.control_code, 0x40);
+    }
+
+    #[async_std::test]
+    async fn test_session_ids_recycled() {
+        let inner = InnerServer::new(2);
+        let mut inner = inner.lock().await;
+        let lock = SharedLock::new();
+        let device = EchoDevice::new_arc();
+
+        // Closed sessions do not count towards the limit, even after ids wrap around
+        let (_, shared, handle) = inner
+            .create_session(
+                SUPPORTED_PROTOCOL,
+                LockHandle::new(lock.clone(), device.clone()),
+            )
+            .unwrap();
+        for _ in 0..u16::MAX {
+            let session = inner.create_session(
+                SUPPORTED_PROTOCOL,
+                LockHandle::new(lock.clone(), device.clone()),
+            );
+            assert!(session.is_ok());
+        }
+
+        // Open sessions do
+        let _session = inner
+            .create_session(
+                SUPPORTED_PROTOCOL,
+                LockHandle::new(lock.clone(), device.clone()),
+            )
+            .unwrap();
+        let session = inner.create_session(
+            SUPPORTED_PROTOCOL,
+            LockHandle::new(lock.clone(), device.clone()),
+        );
+        assert!(matches!(
+            session,
+            Err(Error::Fatal(FatalErrorCode::MaximumClientsExceeded, _))
+        ));
+        drop((shared, handle));
+    }
+
+    #[async_std::test]
+    async fn test_async_initialize_twice() {
+        let addr = serve(ServerConfig::default(), EchoDevice).await;
+        let (_sync, session_id) = initialize(addr).await;
+
+        let (_asynchronous, resp) = async_initialize(addr, session_id).await;
+        assert_eq!(resp.message_type, MessageType::AsyncInitializeResponse);
+
+        // Session already has an asynchronous channel
+        let (_asynchronous, resp) = async_initialize(addr, session_id).await;
+        assert_eq!(resp.message_type, MessageType::FatalError);
+        assert_eq!(
+            resp.control_code,
+            FatalErrorCode::InvalidInitialization.error_code()
+        );
+    }
+
+    #[async_std::test]
+    async fn test_accept_shutdown() {
+        let server = ServerBuilder::default()
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .build();
+        let (sender, receiver) = async_std::channel::bounded(1);
+        let accept = task::spawn(server.accept_until(
+            "127.0.0.1:0",
+            StatusSender::new(),
+            TaskSpawner,
+            async move {
+                let _ = receiver.recv().await;
+            },
+        ));
+
+        sender.send(()).await.unwrap();
+        let res = async_std::future::timeout(Duration::from_secs(1), accept)
+            .await
+            .expect("accept did not stop");
+        assert!(res.is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_accept_multi() {
+        let server = ServerBuilder::default()
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .build();
+
+        // Find two free ports
+        let mut addrs = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+        }
+        task::spawn(server.accept_multi(addrs.clone(), StatusSender::new(), TaskSpawner));
+        task::sleep(Duration::from_millis(100)).await;
+
+        // Channels of a session may connect to different addresses
+        let (_sync, session_id) = initialize(addrs[0]).await;
+        let (_asynchronous, resp) = async_initialize(addrs[1], session_id).await;
+        assert_eq!(resp.message_type, MessageType::AsyncInitializeResponse);
+    }
+
+    #[async_std::test]
+    async fn test_accept_multi_shutdown() {
+        let server = ServerBuilder::default()
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .build();
+
+        let mut addrs = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+        }
+        let (sender, receiver) = async_std::channel::bounded(1);
+        let accept = task::spawn(server.accept_multi_until(
+            addrs.clone(),
+            StatusSender::new(),
+            TaskSpawner,
+            async move {
+                let _ = receiver.recv().await;
+            },
+        ));
+        task::sleep(Duration::from_millis(100)).await;
+
+        // Every listener stops
+        sender.send(()).await.unwrap();
+        let res = async_std::future::timeout(Duration::from_secs(1), accept)
+            .await
+            .expect("accept did not stop");
+        assert!(res.is_ok());
+        for addr in addrs {
+            assert!(TcpStream::connect(addr).await.is_err());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_max_connections() {
+        let server = ServerBuilder::new(ServerConfig::default().max_connections(1))
+            .device(
+                DEFAULT_DEVICE_SUBADRESS.to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .build();
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        task::spawn(server.accept(addr, StatusSender::new(), TaskSpawner));
+        task::sleep(Duration::from_millis(100)).await;
+
+        // Occupies the only connection
+        let idle = TcpStream::connect(addr).await.unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+
+        // Waits while at the limit
+        let mut pending = task::spawn(initialize(addr));
+        assert!(
+            async_std::future::timeout(Duration::from_millis(200), &mut pending)
+                .await
+                .is_err()
+        );
+
+        // Served once the idle connection closes
+        drop(idle);
+        let (_sync, _session_id) = async_std::future::timeout(Duration::from_secs(1), pending)
+            .await
+            .expect("connection not accepted");
+    }
+
+    #[async_std::test]
+    async fn test_io_timeout() {
+        let config = ServerConfig::default().io_timeout(Duration::from_millis(100));
+        let addr = serve(config, EchoDevice).await;
+
+        // Never initialized
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let resp = recv(&mut stream).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+        assert!(Message::read_from(&mut stream, u64::MAX).await.is_err());
+
+        // Idle session
+        let (mut sync, mut asynchronous) = connect(addr).await;
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+        assert!(Message::read_from(&mut sync, u64::MAX).await.is_err());
+        let resp = recv(&mut asynchronous).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+        assert!(Message::read_from(&mut asynchronous, u64::MAX)
+            .await
+            .is_err());
+    }
+
+    async fn authenticate(sync: &mut TcpStream, credentials: &[u8]) -> u8 {
+        MessageType::AuthenticationStart
+            .message_params(0, 0)
+            .with_payload(b"PLAIN".to_vec())
+            .write_to(sync)
+            .await
+            .unwrap();
+        let resp = recv(sync).await;
+        assert_eq!(resp.message_type, MessageType::AuthenticationExchange);
+
+        MessageType::AuthenticationExchange
+            .message_params(0, 0)
+            .with_payload(credentials.to_vec())
+            .write_to(sync)
+            .await
+            .unwrap();
+        let resp = recv(sync).await;
+        assert_eq!(resp.message_type, MessageType::AuthenticationResult);
+        resp.control_code
+    }
+
+    #[async_std::test]
+    async fn test_authentication() {
+        let config = ServerConfig::default().auth(Arc::new(TestAuth));
+        let addr = serve(config, EchoDevice).await;
+        let (mut sync, _asynchronous) = connect(addr).await;
+
+        MessageType::GetSaslMechanismList
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(&mut sync)
+            .await
+            .unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::GetSaslMechanismListResponse);
+        assert_eq!(resp.payload, b"PLAIN");
+
+        // Commands are refused until authenticated
+        let query = MessageType::DataEnd
+            .message_params(0, 0xffffff00)
+            .with_payload(b"*IDN?".to_vec());
+        query.write_to(&mut sync).await.unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+
+        assert_eq!(authenticate(&mut sync, b"\0user\0wrong").await, 0);
+        query.write_to(&mut sync).await.unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::Error);
+
+        assert_eq!(authenticate(&mut sync, b"\0user\0secret").await, 1);
+        query.write_to(&mut sync).await.unwrap();
+        let resp = recv(&mut sync).await;
+        assert_eq!(resp.message_type, MessageType::DataEnd);
+        assert_eq!(resp.payload, b"*IDN?");
+    }
+
+    async fn async_lock(asynchronous: &mut TcpStream, lockstr: &[u8]) -> Message {
+        MessageType::AsyncLock
+            .message_params(1, 0)
+            .with_payload(lockstr.to_vec())
+            .write_to(asynchronous)
+            .await
+            .unwrap();
+        recv(asynchronous).await
+    }
+
+    async fn async_release(asynchronous: &mut TcpStream) -> Message {
+        MessageType::AsyncLock
+            .message_params(0, 0)
+            .no_payload()
+            .write_to(asynchronous)
+            .await
+            .unwrap();
+        recv(asynchronous).await
+    }
+
+    #[async_std::test]
+    async fn test_async_lock_contention() {
+        let addr = serve(ServerConfig::default(), EchoDevice).await;
+        let (_sync1, mut async1) = connect(addr).await;
+        let (_sync2, mut async2) = connect(addr).await;
+
+        // Exclusive lock held by other session
+        let resp = async_lock(&mut async1, b"").await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+        let resp = async_lock(&mut async2, b"foo").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+
+        // Waiting for the lock times out
+        MessageType::AsyncLock
+            .message_params(1, 50)
+            .no_payload()
+            .write_to(&mut async2)
+            .await
+            .unwrap();
+        let resp = recv(&mut async2).await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+
+        let resp = async_release(&mut async1).await;
+        assert_eq!(
+            resp.control_code,
+            ReleaseLockControl::SuccessExclusive as u8
+        );
+
+        // Shared lock held by other session
+        let resp = async_lock(&mut async1, b"foo").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_lock(&mut async2, b"bar").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+
+        // Same lock string shares the lock
+        let resp = async_lock(&mut async2, b"foo").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_release(&mut async2).await;
+        assert_eq!(resp.control_code, ReleaseLockControl::SuccessShared as u8);
+    }
+
+    #[async_std::test]
+    async fn test_async_lock_error() {
+        let addr = serve(ServerConfig::default(), EchoDevice).await;
+        let (_sync, mut asynchronous) = connect(addr).await;
+
+        // Lock string is not valid utf8
+        let resp = async_lock(&mut asynchronous, b"\xff\xfe").await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+        assert_eq!(resp.control_code, RequestLockControl::Error as u8);
+
+        // Already locked
+        let resp = async_lock(&mut asynchronous, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+        let resp = async_lock(&mut asynchronous, b"").await;
+        assert_eq!(resp.control_code, RequestLockControl::Error as u8);
+
+        // Nothing to release
+        let resp = async_release(&mut asynchronous).await;
+        assert_eq!(
+            resp.control_code,
+            ReleaseLockControl::SuccessExclusive as u8
+        );
+        let resp = async_release(&mut asynchronous).await;
+        assert_eq!(resp.control_code, ReleaseLockControl::Error as u8);
+    }
+
+    #[async_std::test]
+    async fn test_authentication_async() {
+        let config = ServerConfig::default().auth(Arc::new(TestAuth));
+        let addr = serve(config, EchoDevice).await;
+
+        // Authenticated session holds an exclusive lock
+        let (mut sync1, mut async1) = connect(addr).await;
+        assert_eq!(authenticate(&mut sync1, b"\0user\0secret").await, 1);
+        let resp = async_lock(&mut async1, b"").await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+        assert_eq!(resp.control_code, RequestLockControl::Success as u8);
+
+        // Asynchronous channel is refused until authenticated
+        let (mut sync2, mut async2) = connect(addr).await;
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.message_type, MessageType::Error);
+
+        // Authentication does not wait for the lock
+        assert_eq!(authenticate(&mut sync2, b"\0user\0secret").await, 1);
+        let resp = async_lock(&mut async2, b"").await;
+        assert_eq!(resp.message_type, MessageType::AsyncLockResponse);
+        assert_eq!(resp.control_code, RequestLockControl::Failure as u8);
+    }
+}
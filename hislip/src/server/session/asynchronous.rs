@@ -10,7 +10,7 @@ use byteorder::{ByteOrder, NetworkEndian};
 use futures::future::Either;
 use futures::lock::Mutex;
 use futures::{pin_mut, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, Stream};
-use lxi_device::lock::{LockHandle, SharedLockError, SharedLockMode, SpinMutex};
+use lxi_device::lock::{LockHandle, RemoteLockHandle, SharedLockError, SharedLockMode, SpinMutex};
 use lxi_device::{Device, DeviceError};
 
 use crate::common::errors::{Error, FatalErrorCode, NonFatalErrorCode};
@@ -18,6 +18,7 @@ use crate::common::messages::{prelude::*, send_fatal, send_nonfatal};
 use crate::common::{Protocol, PROTOCOL_2_0};
 
 use super::{ServerConfig, SharedSession};
+use crate::server::with_io_timeout;
 
 pub(crate) struct AsyncSession<DEV>
 where
@@ -73,7 +74,11 @@ where
         let mut srq_bit = false;
 
         loop {
-            let read_msg = Message::read_from(&mut rd, self.config.max_message_size).fuse();
+            let read_msg = with_io_timeout(
+                self.config.io_timeout,
+                Message::read_from(&mut rd, self.config.max_message_size),
+            )
+            .fuse();
             pin_mut!(read_msg);
 
             let t = match futures::future::select(read_msg, srq.next()).await {
@@ -100,11 +105,42 @@ where
                     // Finish receiving message
                     read_msg.await
                 }
-            }?;
+            };
+            let t = match t {
+                Some(t) => t?,
+                None => {
+                    send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                        &mut wr, NonFatalErrorCode::UnidentifiedError,
+                        "Timed out waiting for message"
+                    );
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            };
+
+            if let (Some(stats), Ok(msg)) = (&self.config.stats, &t) {
+                stats.inbound.record(msg.len());
+            }
+
+            // Client must authenticate on the synchronous channel before using the device
+            let authenticated =
+                self.config.auth.is_none() || self.shared.lock().await.is_authenticated();
 
             match t {
                 Ok(msg) => {
                     match msg {
+                        Message {
+                            message_type:
+                                MessageType::AsyncLock
+                                | MessageType::AsyncRemoteLocalControl
+                                | MessageType::AsyncDeviceClear
+                                | MessageType::AsyncStatusQuery,
+                            ..
+                        } if !authenticated => {
+                            send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                                &mut wr, NonFatalErrorCode::UnidentifiedError,
+                                "Authentication required"
+                            );
+                        }
                         Message {
                             message_type: MessageType::VendorSpecific(code),
                             ..
@@ -174,12 +210,13 @@ where
 
                                         log::debug!(peer=peer.to_string(), session_id=self.id, timeout=timeout; "Async lock: {:?}", lockstr);
                                         // Try to acquire lock
-                                        let mut handle = self.handle.lock();
+                                        let handle = RemoteLockHandle::new(self.handle.clone());
                                         let res = if timeout == 0 {
                                             // Try to lock immediately
                                             handle.try_acquire(lockstr.as_bytes())
                                         } else {
                                             // Try to acquire lock before timeout
+                                            // Handle is not held while waiting so that the synchronous channel may keep using it
                                             // TODO: Cannot be cancelled by AsyncClearDevice
                                             future::timeout(
                                                 Duration::from_millis(timeout as u64),
@@ -217,56 +254,76 @@ where
                         } => {
                             log::debug!(peer=peer.to_string(), session_id=self.id, message_id=message_id; "Remote/local request = {}", request);
                             let mut shared = self.shared.lock().await;
-                            let handle = self.handle.lock();
-                            let res = match request {
-                                0 => {
-                                    // Disable remote
-                                    shared.enable_remote = false;
-                                    let mut dev = handle.async_lock().await.unwrap();
-                                    dev.set_local_lockout(false);
-                                    dev.set_remote(false)
-                                }
-                                1 => {
-                                    // Enable remote
-                                    shared.enable_remote = true;
-                                    Ok(())
-                                }
-                                2 => {
-                                    // Disable remote and go to local
-                                    shared.enable_remote = false;
-                                    let mut dev = handle.async_lock().await.unwrap();
-                                    dev.set_local_lockout(false);
-                                    dev.set_remote(false)
-                                }
-                                3 => {
-                                    //Enable remote and go to remote
-                                    shared.enable_remote = true;
-                                    let mut dev = handle.async_lock().await.unwrap();
-                                    dev.set_remote(false)
-                                }
-                                4 => {
-                                    // Enable remote and lock out local
-                                    shared.enable_remote = true;
-                                    let mut dev = handle.async_lock().await.unwrap();
-                                    dev.set_local_lockout(true);
-                                    Ok(())
-                                }
-                                5 => {
-                                    // Enable remote, got to remote, and set local lockout
-                                    shared.enable_remote = true;
-                                    let mut dev = handle.async_lock().await.unwrap();
-                                    dev.set_local_lockout(true);
-                                    dev.set_remote(true)
-                                }
-                                6 => {
-                                    // Go to local without changing state of remote enable
-                                    let mut dev = handle.async_lock().await.unwrap();
-                                    dev.set_remote(false)
+                            let handle = RemoteLockHandle::new(self.handle.clone());
+                            let res = async {
+                                match request {
+                                    0 => {
+                                        // Disable remote
+                                        shared.enable_remote = false;
+                                        let mut dev = handle
+                                            .async_lock()
+                                            .await
+                                            .map_err(|_| DeviceError::IoError)?;
+                                        dev.set_local_lockout(false);
+                                        dev.set_remote(false)
+                                    }
+                                    1 => {
+                                        // Enable remote
+                                        shared.enable_remote = true;
+                                        Ok(())
+                                    }
+                                    2 => {
+                                        // Disable remote and go to local
+                                        shared.enable_remote = false;
+                                        let mut dev = handle
+                                            .async_lock()
+                                            .await
+                                            .map_err(|_| DeviceError::IoError)?;
+                                        dev.set_local_lockout(false);
+                                        dev.set_remote(false)
+                                    }
+                                    3 => {
+                                        //Enable remote and go to remote
+                                        shared.enable_remote = true;
+                                        let mut dev = handle
+                                            .async_lock()
+                                            .await
+                                            .map_err(|_| DeviceError::IoError)?;
+                                        dev.set_remote(true)
+                                    }
+                                    4 => {
+                                        // Enable remote and lock out local
+                                        shared.enable_remote = true;
+                                        let mut dev = handle
+                                            .async_lock()
+                                            .await
+                                            .map_err(|_| DeviceError::IoError)?;
+                                        dev.set_local_lockout(true);
+                                        Ok(())
+                                    }
+                                    5 => {
+                                        // Enable remote, got to remote, and set local lockout
+                                        shared.enable_remote = true;
+                                        let mut dev = handle
+                                            .async_lock()
+                                            .await
+                                            .map_err(|_| DeviceError::IoError)?;
+                                        dev.set_local_lockout(true);
+                                        dev.set_remote(true)
+                                    }
+                                    6 => {
+                                        // Go to local without changing state of remote enable
+                                        let mut dev = handle
+                                            .async_lock()
+                                            .await
+                                            .map_err(|_| DeviceError::IoError)?;
+                                        dev.set_remote(false)
+                                    }
+                                    _ => Err(DeviceError::NotSupported),
                                 }
-                                _ => Err(DeviceError::NotSupported),
-                            };
+                            }
+                            .await;
                             drop(shared);
-                            drop(handle);
 
                             match res {
                                 Ok(_) => {
@@ -351,7 +408,7 @@ where
 
                             let stb = {
                                 let shared = self.shared.lock().await;
-                                let handle = self.handle.lock();
+                                let handle = RemoteLockHandle::new(self.handle.clone());
                                 let mut dev = handle.inner_lock().await;
 
                                 // Calculate MAV bit
@@ -364,7 +421,11 @@ where
                                 }
 
                                 // Get status of device
-                                dev.get_status().unwrap_or(0) & 0xef | mav
+                                let stb = dev.get_status().unwrap_or_else(|err| {
+                                    log::warn!(session_id=self.id; "Failed to get status: {:?}", err);
+                                    0
+                                });
+                                stb & 0xef | mav
                             };
 
                             srq_bit = false;
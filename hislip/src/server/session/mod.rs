@@ -1,4 +1,5 @@
 use async_std::channel::{self, Receiver, Sender};
+use lxi_device::status::Receiver as StatusReceiver;
 
 use super::ServerConfig;
 use crate::common::Protocol;
@@ -6,6 +7,9 @@ use crate::common::Protocol;
 pub(crate) mod asynchronous;
 pub(crate) mod synchronous;
 
+/// Message id of the first message sent by a client, also used after a device clear
+pub(crate) const INITIAL_MESSAGE_ID: u32 = 0xffff_ff00;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SessionMode {
     Synchronized,
@@ -36,8 +40,15 @@ pub(crate) struct SharedSession {
 
     clear: (Sender<()>, Receiver<()>),
 
-    read_message_id: u32,
+    /// Id of the last message read, `None` until the client has sent a message
+    read_message_id: Option<u32>,
     sent_message_id: u32,
+
+    /// Service requests of the session's device, if it has its own status sender
+    srq: Option<StatusReceiver>,
+
+    /// Client has authenticated on the synchronous channel
+    authenticated: bool,
 }
 
 impl SharedSession {
@@ -48,9 +59,11 @@ impl SharedSession {
             mode: SessionMode::Overlapped,
             max_message_size: 256,
             clear: channel::bounded(1),
-            read_message_id: 0,
+            read_message_id: None,
             enable_remote: true,
             sent_message_id: 0,
+            srq: None,
+            authenticated: false,
         }
     }
 
@@ -62,11 +75,7 @@ impl SharedSession {
 
     #[must_use]
     pub(crate) fn is_initialized(&self) -> bool {
-        // !matches!(self.state, SessionState::Handshake) // Just looks weird
-        match self.state {
-            SessionState::Handshake => false,
-            _ => false,
-        }
+        self.state != SessionState::Handshake
     }
 
     /// Get the session's protocol.
@@ -80,6 +89,12 @@ impl SharedSession {
         self.state = state;
     }
 
+    /// Restart message id tracking, used after a device clear.
+    pub(crate) fn reset_message_ids(&mut self) {
+        self.read_message_id = None;
+        self.sent_message_id = 0;
+    }
+
     pub(crate) fn get_clear_receiver(&self) -> Receiver<()> {
         self.clear.1.clone()
     }
@@ -87,4 +102,23 @@ impl SharedSession {
     pub(crate) fn get_clear_sender(&self) -> Sender<()> {
         self.clear.0.clone()
     }
+
+    pub(crate) fn set_srq_receiver(&mut self, srq: StatusReceiver) {
+        self.srq = Some(srq);
+    }
+
+    pub(crate) fn take_srq_receiver(&mut self) -> Option<StatusReceiver> {
+        self.srq.take()
+    }
+
+    /// Check if the client has authenticated.
+    #[must_use]
+    pub(crate) fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Set if the client has authenticated.
+    pub(crate) fn set_authenticated(&mut self, authenticated: bool) {
+        self.authenticated = authenticated;
+    }
 }
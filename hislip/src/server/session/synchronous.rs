@@ -1,20 +1,26 @@
 use std::io;
+use std::mem;
 use std::str::from_utf8;
 
 use async_std::channel::Receiver;
+use async_std::future;
 use async_std::sync::Arc;
+use futures::io::ReadHalf;
 use futures::lock::Mutex;
-use futures::{select, AsyncRead, AsyncWrite, AsyncWriteExt, FutureExt};
-use lxi_device::lock::RemoteLockHandle;
+use futures::{pin_mut, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, StreamExt};
+#[cfg(feature = "fault-injection")]
+use lxi_device::fault::Fault;
+use lxi_device::lock::{RemoteLockHandle, SharedLockError};
 use lxi_device::trigger::Source;
-use lxi_device::Device;
+use lxi_device::{AsyncDevice, AsyncOutput, Device};
 
 use crate::common::errors::{Error, FatalErrorCode, NonFatalErrorCode};
 use crate::common::messages::{prelude::*, send_fatal, send_nonfatal};
 use crate::common::{Protocol, PROTOCOL_2_0};
 
 use super::{ServerConfig, SharedSession};
-use crate::server::session::{SessionMode, SessionState};
+use crate::server::session::{SessionMode, SessionState, INITIAL_MESSAGE_ID};
+use crate::server::{auth, with_io_timeout};
 
 pub(crate) struct SyncSession<DEV>
 where
@@ -37,7 +43,7 @@ where
 
 impl<DEV> SyncSession<DEV>
 where
-    DEV: Device,
+    DEV: AsyncDevice,
 {
     pub(crate) fn new(
         id: u16,
@@ -62,7 +68,7 @@ where
         control_code: u8,
     ) -> Result<(), io::Error>
     where
-        S: AsyncRead + AsyncWrite + Unpin,
+        S: AsyncWrite + Unpin,
     {
         let mut shared = self.shared.lock().await;
         let feature_request = FeatureBitmap(control_code);
@@ -80,6 +86,7 @@ where
         // Agreed features
         let feature_setting = FeatureBitmap::new(feature_request.overlapped(), false, false);
         let sent_message_id = shared.sent_message_id;
+        shared.reset_message_ids();
         drop(shared);
 
         MessageType::DeviceClearAcknowledge
@@ -89,78 +96,232 @@ where
             .await
     }
 
+    /// Discard messages until device clear has been completed.
+    /// Returns `true` once the clear has been acknowledged.
     async fn clear_buffer<S>(
         &self,
         mut stream: S,
         peer: String,
-        mut msg: Result<Message, Error>,
-    ) -> Result<(), io::Error>
+        msg: Result<Message, Error>,
+    ) -> Result<bool, io::Error>
     where
-        S: AsyncRead + AsyncWrite + Unpin,
+        S: AsyncWrite + Unpin,
     {
-        loop {
-            match msg {
-                Ok(Message {
-                    message_type: MessageType::DeviceClearComplete,
-                    control_code,
-                    ..
-                }) => {
-                    if self.handle.can_lock().is_ok() {
-                        let mut dev = self.handle.inner_lock().await;
-                        let _res = dev.clear();
-                    }
-
-                    break self
-                        .acknowledge_device_clear(stream, peer, control_code)
-                        .await;
+        match msg {
+            Ok(Message {
+                message_type: MessageType::DeviceClearComplete,
+                control_code,
+                ..
+            }) => {
+                if self.handle.can_lock().is_ok() {
+                    let mut dev = self.handle.inner_lock().await;
+                    let _res = dev.clear();
                 }
-                // Ignore other messages
-                Ok(_) => {}
-                // Invalid message
-                Err(err) => {
-                    if err.is_fatal() {
-                        Message::from(err).write_to(&mut stream).await?;
-                        return Err(io::ErrorKind::Other.into());
-                    } else {
-                        Message::from(err).write_to(&mut stream).await?;
-                    }
+
+                self.acknowledge_device_clear(stream, peer, control_code)
+                    .await?;
+                Ok(true)
+            }
+            // Ignore other messages
+            Ok(_) => Ok(false),
+            // Invalid message
+            Err(err) => {
+                if err.is_fatal() {
+                    Message::from(err).write_to(&mut stream).await?;
+                    Err(io::ErrorKind::Other.into())
+                } else {
+                    Message::from(err).write_to(&mut stream).await?;
+                    Ok(false)
                 }
             }
-            msg = Message::read_from(&mut stream, self.config.max_message_size).await?;
         }
     }
 
     pub(crate) async fn handle_session<S>(
         self,
-        mut stream: S,
+        stream: S,
         peer: String,
         protocol: Protocol,
     ) -> Result<(), io::Error>
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
+        let (rd, mut stream) = stream.split();
+
+        // Next message, kept pending between messages so that it can be polled while sending a response
+        let max_message_size = self.config.max_message_size;
+        let read = move |mut rd: ReadHalf<S>| async move {
+            let msg = Message::read_from(&mut rd, max_message_size).await;
+            (rd, msg)
+        };
+        let next = read(rd).fuse();
+        pin_mut!(next);
+
+        // Message received while sending a response
+        let mut received = None;
+
         // Data buffer
         let mut buffer: Vec<u8> = Vec::new();
 
+        // Discarding messages until device clear is complete
+        let mut clearing = false;
+
+        // Client authentication (SASL PLAIN)
+        let mut authenticating = false;
+        let mut authenticated = self.config.auth.is_none();
+
+        #[cfg(feature = "fault-injection")]
+        let mut responses = 0u32;
+
         loop {
-            let msg = Message::read_from(&mut stream, self.config.max_message_size).await?;
+            let msg = match received.take() {
+                Some(msg) => msg,
+                None => {
+                    let (rd, msg) = match with_io_timeout(self.config.io_timeout, &mut next).await {
+                        Some(res) => res,
+                        None => {
+                            send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                                &mut stream, NonFatalErrorCode::UnidentifiedError,
+                                "Timed out waiting for message"
+                            );
+                            return Err(io::ErrorKind::TimedOut.into());
+                        }
+                    };
+                    next.set(read(rd).fuse());
+                    msg
+                }
+            }?;
+            if let (Some(stats), Ok(msg)) = (&self.config.stats, &msg) {
+                stats.inbound.record(msg.len());
+            }
 
             // Check if a clear device is in progress before waiting for a lock
-            if let Ok(_abort) = self.clear.try_recv() {
+            if clearing || self.clear.try_recv().is_ok() {
                 // Clear buffer
                 buffer.clear();
-                self.clear_buffer(&mut stream, peer.clone(), msg).await?;
+                clearing = !self.clear_buffer(&mut stream, peer.clone(), msg).await?;
                 continue;
             }
 
+            // Authentication does not use the device, answer it without waiting for a lock
+            let msg = match msg {
+                Ok(msg)
+                    if protocol >= PROTOCOL_2_0
+                        && matches!(
+                            msg.message_type,
+                            MessageType::GetSaslMechanismList
+                                | MessageType::AuthenticationStart
+                                | MessageType::AuthenticationExchange
+                        ) =>
+                {
+                    match msg {
+                        Message {
+                            message_type: MessageType::GetSaslMechanismList,
+                            ..
+                        } if self.config.auth.is_some() => {
+                            log::debug!(peer=peer.to_string(), session_id=self.id; "Get SASL mechanisms");
+
+                            MessageType::GetSaslMechanismListResponse
+                                .message_params(0, 0)
+                                .with_payload(auth::MECHANISMS.to_vec())
+                                .write_to(&mut stream)
+                                .await?;
+                        }
+                        Message {
+                            message_type: MessageType::AuthenticationStart,
+                            payload: mechanism,
+                            ..
+                        } if self.config.auth.is_some() => {
+                            log::debug!(peer=peer.to_string(), session_id=self.id; "Authentication start: {}", String::from_utf8_lossy(&mechanism));
+
+                            authenticating = mechanism == b"PLAIN";
+                            if authenticating {
+                                // Empty challenge, client responds with credentials
+                                MessageType::AuthenticationExchange
+                                    .message_params(0, 0)
+                                    .no_payload()
+                                    .write_to(&mut stream)
+                                    .await?;
+                            } else {
+                                MessageType::AuthenticationResult
+                                    .message_params(AuthenticationResultControl::Failure as u8, 0)
+                                    .no_payload()
+                                    .write_to(&mut stream)
+                                    .await?;
+                            }
+                        }
+                        Message {
+                            message_type: MessageType::AuthenticationExchange,
+                            payload,
+                            ..
+                        } if self.config.auth.is_some() => {
+                            if authenticating {
+                                authenticated =
+                                    match (&self.config.auth, auth::parse_plain(&payload)) {
+                                        (Some(storage), Some((authzid, authcid, password))) => {
+                                            storage.authenticate(authzid, authcid, password)
+                                        }
+                                        _ => false,
+                                    };
+                            }
+                            authenticating = false;
+                            self.shared.lock().await.set_authenticated(authenticated);
+                            log::debug!(peer=peer.to_string(), session_id=self.id; "Authenticated: {}", authenticated);
+
+                            let control = if authenticated {
+                                AuthenticationResultControl::Success
+                            } else {
+                                AuthenticationResultControl::Failure
+                            };
+                            MessageType::AuthenticationResult
+                                .message_params(control as u8, 0)
+                                .no_payload()
+                                .write_to(&mut stream)
+                                .await?;
+                        }
+                        _ => {
+                            log::debug!(peer=peer.to_string(), session_id=self.id; "Authentication Start/Exchange");
+
+                            send_fatal!(
+                                &mut stream,
+                                FatalErrorCode::SecureConnectionFailed,
+                                "Secure connection not supported"
+                            )
+                        }
+                    }
+                    continue;
+                }
+                msg => msg,
+            };
+
             // Wait for device becoming available or a lock is acquired
-            // Abort the lock attempt if a clear device is started
-            let mut dev = select! {
-                res = self.handle.async_lock().fuse() => res.unwrap(),
-                _abort = self.clear.recv().fuse() => {
+            // Abort the lock attempt if a clear device is started or the lock timeout expires
+            let cancel = async {
+                match self.config.lock_timeout {
+                    Some(t) => future::timeout(t, self.clear.recv())
+                        .await
+                        .map_or(SharedLockError::Timeout, |_| SharedLockError::Aborted),
+                    None => {
+                        let _ = self.clear.recv().await;
+                        SharedLockError::Aborted
+                    }
+                }
+            };
+            let mut dev = match self.handle.async_lock_cancellable(cancel).await {
+                Ok(dev) => dev,
+                Err(SharedLockError::Aborted) => {
                     // Clear buffer
                     buffer.clear();
-                    self.clear_buffer(&mut stream, peer.clone(), msg).await?;
+                    clearing = !self.clear_buffer(&mut stream, peer.clone(), msg).await?;
+                    continue;
+                }
+                Err(err) => {
+                    // Drop the message and any partial command but keep the session alive
+                    buffer.clear();
+                    send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                        &mut stream, NonFatalErrorCode::UnidentifiedError,
+                        "Failed to lock device: {:?}", err
+                    );
                     continue;
                 }
             };
@@ -170,6 +331,16 @@ where
                 // Valid message
                 Ok(msg) => {
                     match msg {
+                        Message {
+                            message_type:
+                                MessageType::Data | MessageType::DataEnd | MessageType::Trigger,
+                            ..
+                        } if !authenticated => {
+                            send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                                &mut stream, NonFatalErrorCode::UnidentifiedError,
+                                "Authentication required"
+                            );
+                        }
                         Message {
                             message_type: MessageType::VendorSpecific(code),
                             ..
@@ -217,7 +388,7 @@ where
                             match state {
                                 // Normal state
                                 SessionState::Normal => {
-                                    shared.read_message_id = message_id;
+                                    shared.read_message_id = Some(message_id);
 
                                     if buffer.try_reserve_exact(data.len()).is_err() {
                                         send_fatal!(peer=peer.to_string(), session_id=self.id;
@@ -231,42 +402,120 @@ where
                                     if is_end {
                                         log::debug!(peer=peer.to_string(), session_id=self.id, message_id=message_id; "Data END, {}", control);
 
-                                        let data = if buffer.eq_ignore_ascii_case(b"*idn?")
+                                        // Go to remote if enabled by client
+                                        if shared.enable_remote {
+                                            let _res = dev.set_remote(true);
+                                        }
+
+                                        let cmd = mem::take(&mut buffer);
+                                        let data = if cmd.eq_ignore_ascii_case(b"*idn?")
                                             && self.config.short_idn.is_some()
                                         {
-                                            self.config.short_idn.clone()
+                                            self.config.short_idn.clone().map(AsyncOutput::from)
                                         } else {
-                                            let data = dev.execute(&buffer);
-                                            buffer.clear();
-                                            data
+                                            AsyncDevice::execute_async(dev, cmd).await
+                                        };
+
+                                        #[cfg(feature = "fault-injection")]
+                                        let data = match data {
+                                            Some(data) => {
+                                                responses = responses.wrapping_add(1);
+                                                if let Some(delay) = self.config.faults.delay {
+                                                    async_std::task::sleep(delay).await;
+                                                }
+                                                match self.config.faults.fault(responses) {
+                                                    Fault::None => Some(data),
+                                                    Fault::Drop => {
+                                                        log::debug!(peer=peer.to_string(), session_id=self.id, message_id=message_id; "Dropping response (injected)");
+                                                        None
+                                                    }
+                                                    Fault::Error => {
+                                                        send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                                                            &mut stream,
+                                                            NonFatalErrorCode::UnidentifiedError,
+                                                            "Injected fault"
+                                                        );
+                                                        None
+                                                    }
+                                                }
+                                            }
+                                            None => None,
                                         };
 
                                         // Send back response
-                                        if let Some(data) = data {
-                                            let mut chunks = data
-                                                .chunks(shared.max_message_size as usize)
-                                                .peekable();
+                                        if let Some(mut data) = data {
+                                            // Always make progress even if client asks for empty messages
+                                            let max_message_size =
+                                                (shared.max_message_size as usize).max(1);
+                                            let interruptible = self.config.allow_interrupt
+                                                && shared.mode == SessionMode::Overlapped;
                                             drop(shared);
 
-                                            while let Some(chunk) = chunks.next() {
-                                                // Stop sending if a clear has been received on async channel
-                                                if self.clear.try_recv().is_ok() {
-                                                    break;
-                                                }
+                                            // Look ahead one chunk to find the end, the length declared by
+                                            // the device is not trusted
+                                            let mut next_chunk = data.chunks.next().await;
+                                            'send: while let Some(chunk) = next_chunk.take() {
+                                                next_chunk = data.chunks.next().await;
+                                                let last_chunk = next_chunk.is_none();
 
-                                                // Peek if next chunk exists, if not, mark data as end
-                                                let end = chunks.peek().is_none();
-                                                let msg = if end {
-                                                    MessageType::DataEnd
-                                                } else {
-                                                    MessageType::Data
-                                                };
-
-                                                // Send message
-                                                msg.message_params(0, message_id)
-                                                    .with_payload(chunk.to_vec())
-                                                    .write_to(&mut stream)
-                                                    .await?;
+                                                // An empty last chunk still ends the response
+                                                if chunk.is_empty() && !last_chunk {
+                                                    continue;
+                                                }
+                                                let num_parts =
+                                                    chunk.len().div_ceil(max_message_size).max(1);
+                                                let parts = chunk
+                                                    .chunks(max_message_size)
+                                                    .chain(chunk.is_empty().then_some(&chunk[..]));
+
+                                                for (i, part) in parts.enumerate() {
+                                                    // Stop sending if a clear has been received on async channel
+                                                    if self.clear.try_recv().is_ok() {
+                                                        clearing = true;
+                                                        break 'send;
+                                                    }
+
+                                                    // Abandon response if client has sent a new message
+                                                    if interruptible && received.is_none() {
+                                                        if let Some((rd, msg)) =
+                                                            (&mut next).now_or_never()
+                                                        {
+                                                            next.set(read(rd).fuse());
+                                                            let interrupt = matches!(
+                                                                msg,
+                                                                Ok(Ok(Message {
+                                                                    message_type: MessageType::Data
+                                                                        | MessageType::DataEnd
+                                                                        | MessageType::Trigger,
+                                                                    ..
+                                                                }))
+                                                            );
+                                                            received = Some(msg);
+                                                            if interrupt {
+                                                                log::debug!(peer=peer.to_string(), session_id=self.id, message_id=message_id; "Interrupted");
+                                                                MessageType::Interrupted
+                                                                    .message_params(0, message_id)
+                                                                    .no_payload()
+                                                                    .write_to(&mut stream)
+                                                                    .await?;
+                                                                break 'send;
+                                                            }
+                                                        }
+                                                    }
+
+                                                    // Mark data as end if nothing remains
+                                                    let msg = if last_chunk && i + 1 == num_parts {
+                                                        MessageType::DataEnd
+                                                    } else {
+                                                        MessageType::Data
+                                                    };
+
+                                                    // Send message
+                                                    msg.message_params(0, message_id)
+                                                        .with_payload(part.to_vec())
+                                                        .write_to(&mut stream)
+                                                        .await?;
+                                                }
                                             }
                                         }
                                     } else {
@@ -292,16 +541,38 @@ where
                             ..
                         } => {
                             let mut inner = self.shared.lock().await;
-                            inner.read_message_id = message_id;
                             let state = inner.state();
-                            drop(inner);
+
+                            // Trigger takes the id following the last command, or the id of a partial command
+                            let expected = match inner.read_message_id {
+                                Some(id) if buffer.is_empty() => id.wrapping_add(2),
+                                Some(id) => id,
+                                None => INITIAL_MESSAGE_ID,
+                            };
 
                             match state {
+                                SessionState::Normal if message_id != expected => {
+                                    drop(inner);
+                                    send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                                        &mut stream,
+                                        NonFatalErrorCode::UnidentifiedError,
+                                        "Trigger message id {:#010x} out of sequence, expected {:#010x}", message_id, expected
+                                    );
+                                }
                                 SessionState::Normal => {
+                                    inner.read_message_id = Some(message_id);
+                                    drop(inner);
+
                                     let control = RmtDeliveredControl(control_code);
                                     log::debug!(session_id=self.id, message_id=message_id; "Trigger, {}", control);
 
-                                    let _ = dev.trigger(Source::Bus);
+                                    if let Err(err) = dev.trigger(Source::Bus) {
+                                        send_nonfatal!(peer=peer.to_string(), session_id=self.id;
+                                            &mut stream,
+                                            NonFatalErrorCode::UnidentifiedError,
+                                            "Trigger failed: {:?}", err
+                                        );
+                                    }
                                 }
                                 // Initial handshake
                                 SessionState::Handshake => {
@@ -327,28 +598,20 @@ where
                         Message {
                             message_type: MessageType::GetDescriptors,
                             ..
-                        } => {}
-                        Message {
-                            message_type: MessageType::StartTLS | MessageType::EndTLS,
-                            ..
                         } if protocol >= PROTOCOL_2_0 => {
-                            log::debug!(peer=peer.to_string(), session_id=self.id; "Start/end TLS");
+                            log::debug!(peer=peer.to_string(), session_id=self.id; "Get descriptors");
 
-                            send_fatal!(
-                                &mut stream,
-                                FatalErrorCode::SecureConnectionFailed,
-                                "Secure connection not supported"
-                            )
+                            MessageType::GetDescriptorsResponse
+                                .message_params(0, self.config.descriptors)
+                                .no_payload()
+                                .write_to(&mut stream)
+                                .await?;
                         }
                         Message {
-                            message_type:
-                                MessageType::GetSaslMechanismList
-                                | MessageType::AuthenticationStart
-                                | MessageType::AuthenticationExchange,
-                            payload: _data,
+                            message_type: MessageType::StartTLS | MessageType::EndTLS,
                             ..
                         } if protocol >= PROTOCOL_2_0 => {
-                            log::debug!(peer=peer.to_string(), session_id=self.id; "Authentication Start/Exchange");
+                            log::debug!(peer=peer.to_string(), session_id=self.id; "Start/end TLS");
 
                             send_fatal!(
                                 &mut stream,
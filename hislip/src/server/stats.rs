@@ -0,0 +1,103 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_std::sync::Arc;
+use byteorder::{ByteOrder, NetworkEndian};
+use futures::{AsyncRead, AsyncWrite};
+use lxi_device::stats::MessageStats;
+
+use crate::common::messages::Message;
+
+/// Stream recording the size of every message written to it as outbound statistics.
+///
+/// Messages are found by following the headers in the written data, so it works no matter
+/// how a message is split into writes.
+pub(crate) struct StatsStream<S> {
+    inner: S,
+    stats: Option<Arc<MessageStats>>,
+    /// Header of the message being written
+    header: [u8; Message::MESSAGE_HEADER_SIZE],
+    header_len: usize,
+    /// Payload left of the message being written
+    remaining: u64,
+}
+
+impl<S> StatsStream<S> {
+    pub(crate) fn new(inner: S, stats: Option<Arc<MessageStats>>) -> Self {
+        Self {
+            inner,
+            stats,
+            header: [0u8; Message::MESSAGE_HEADER_SIZE],
+            header_len: 0,
+            remaining: 0,
+        }
+    }
+
+    fn record(&mut self, mut written: &[u8]) {
+        if let Some(stats) = &self.stats {
+            while !written.is_empty() {
+                if self.remaining > 0 {
+                    // Skip payload
+                    let n = self.remaining.min(written.len() as u64) as usize;
+                    self.remaining -= n as u64;
+                    written = &written[n..];
+                } else {
+                    // Collect header
+                    let n = (self.header.len() - self.header_len).min(written.len());
+                    self.header[self.header_len..self.header_len + n]
+                        .copy_from_slice(&written[..n]);
+                    self.header_len += n;
+                    written = &written[n..];
+
+                    if self.header_len == self.header.len() {
+                        self.header_len = 0;
+                        self.remaining = NetworkEndian::read_u64(&self.header[8..16]);
+                        stats
+                            .outbound
+                            .record(Message::MESSAGE_HEADER_SIZE + self.remaining as usize);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S> AsyncRead for StatsStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for StatsStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.record(&buf[..n]);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
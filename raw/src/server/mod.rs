@@ -1,21 +1,23 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::time::Duration;
 
 use async_std::path::Path;
 use async_std::sync::Arc;
 use async_std::task;
 use futures::{lock::Mutex, AsyncReadExt};
-use futures::{AsyncBufReadExt, AsyncWriteExt, StreamExt};
+use futures::{pin_mut, AsyncBufReadExt, AsyncWriteExt, Stream, StreamExt};
 
 use async_std::io::{self, BufReader, Read, Write};
 use async_std::net::{TcpListener, ToSocketAddrs};
 
-use async_listen::ListenExt;
+use async_listen::{backpressure::Token, ListenExt};
 
+#[cfg(feature = "fault-injection")]
+use lxi_device::fault::{Fault, FaultInjection};
 use lxi_device::lock::SpinMutex;
 use lxi_device::{
-    lock::{LockHandle, SharedLock},
-    Device,
+    lock::{LockHandle, SharedLock, SharedLockError},
+    AsyncDevice, AsyncOutput,
 };
 
 #[cfg(unix)]
@@ -32,37 +34,28 @@ impl Server {
         device: Arc<Mutex<DEV>>,
     ) -> io::Result<()>
     where
-        DEV: Device + Send + 'static,
+        DEV: AsyncDevice,
     {
         let listener = TcpListener::bind(addr).await?;
-        let mut incoming = listener
+        let local = listener.local_addr()?;
+        let incoming = listener
             .incoming()
-            .log_warnings(|warn| log::warn!("Listening error: {}", warn))
+            .log_warnings(move |warn| log::warn!("{:?} listening error: {}", local, warn))
             .handle_errors(Duration::from_millis(100))
-            .backpressure(self.0.limit);
-
-        while let Some((token, stream)) = incoming.next().await {
-            let s = self.clone();
-            let peer = stream.peer_addr()?;
-            log::error!("Accepted from: {}", peer);
-
-            let shared_lock = shared_lock.clone();
-            let device = device.clone();
-
-            stream.set_nodelay(true)?;
-
-            task::spawn(async move {
-                let (reader, writer) = stream.split();
-                if let Err(err) = s
-                    .process_client(reader, writer, shared_lock, device, peer)
-                    .await
-                {
-                    log::warn!("Error processing client: {}", err)
+            .backpressure(self.0.limit)
+            .filter_map(|(token, stream)| async move {
+                let peer = stream
+                    .peer_addr()
+                    .map_err(|err| log::warn!("Failed to get peer address: {}", err))
+                    .ok()?;
+                if let Err(err) = stream.set_nodelay(true) {
+                    log::warn!("{:?} failed to set nodelay: {}", peer, err);
                 }
-                drop(token);
+                let (reader, writer) = stream.split();
+                Some((token, (reader, writer, peer)))
             });
-        }
-        Ok(())
+
+        self.accept_incoming(incoming, shared_lock, device).await
     }
 
     /// Listen to a unix socket for client
@@ -74,26 +67,52 @@ impl Server {
         device: Arc<Mutex<DEV>>,
     ) -> io::Result<()>
     where
-        DEV: Device + Send + 'static,
+        DEV: AsyncDevice,
     {
         let listener = UnixListener::bind(path).await?;
         let local = listener.local_addr()?;
-        let mut incoming = listener
+        let incoming = listener
             .incoming()
-            .log_warnings(|warn| log::error!("{:?} listening error: {}", local, warn))
+            .log_warnings(move |warn| log::warn!("{:?} listening error: {}", local, warn))
             .handle_errors(Duration::from_millis(100))
-            .backpressure(self.0.limit);
+            .backpressure(self.0.limit)
+            .filter_map(|(token, stream)| async move {
+                let peer = stream
+                    .peer_addr()
+                    .map_err(|err| log::warn!("Failed to get peer address: {}", err))
+                    .ok()?;
+                let (reader, writer) = stream.split();
+                Some((token, (reader, writer, peer)))
+            });
 
-        while let Some((token, stream)) = incoming.next().await {
+        self.accept_incoming(incoming, shared_lock, device).await
+    }
+
+    /// Serve clients from a stream of accepted connections
+    ///
+    /// Each client is processed in a separate task, the backpressure `token` is held until it disconnects.
+    pub async fn accept_incoming<DEV, S, RD, WR, SA>(
+        self: Arc<Self>,
+        incoming: S,
+        shared_lock: Arc<SpinMutex<SharedLock>>,
+        device: Arc<Mutex<DEV>>,
+    ) -> io::Result<()>
+    where
+        DEV: AsyncDevice,
+        S: Stream<Item = (Token, (RD, WR, SA))>,
+        RD: Read + Unpin + Send + 'static,
+        WR: Write + Unpin + Send + 'static,
+        SA: Debug + Send + 'static,
+    {
+        pin_mut!(incoming);
+        while let Some((token, (reader, writer, peer))) = incoming.next().await {
             let s = self.clone();
-            let peer = stream.peer_addr()?;
             log::info!("Accepted from: {:?}", peer);
 
             let shared_lock = shared_lock.clone();
             let device = device.clone();
 
             task::spawn(async move {
-                let (reader, writer) = stream.split();
                 if let Err(err) = s
                     .process_client(reader, writer, shared_lock, device, peer)
                     .await
@@ -116,7 +135,7 @@ impl Server {
         peer: SA,
     ) -> io::Result<()>
     where
-        DEV: Device + Send,
+        DEV: AsyncDevice,
         RD: Read + Unpin,
         WR: Write + Unpin,
         SA: Debug,
@@ -128,9 +147,18 @@ impl Server {
 
         let handle = LockHandle::new(shared_lock, device);
 
+        #[cfg(feature = "fault-injection")]
+        let mut responses = 0u32;
+
         loop {
+            // Clear previous message
+            cmd.clear();
+
             // Read a line from stream.
-            let n = reader.read_until(self.0.read_termination, &mut cmd).await?;
+            let n = match self.0.read_termination.byte() {
+                Some(term) => reader.read_until(term, &mut cmd).await?,
+                None => reader.read_to_end(&mut cmd).await?,
+            };
             if n == 0 {
                 log::info!("{:?} disconnected", peer);
                 break;
@@ -138,35 +166,148 @@ impl Server {
 
             log::trace!("{:?} read {} bytes", peer, cmd.len());
 
-            let resp = {
-                let mut device = handle.async_lock().await.unwrap();
-                cmd.pop(); // Remove read_termination
-                device.execute(&cmd)
+            // Remove read_termination
+            self.0.read_termination.strip(&mut cmd);
+
+            let resp = match (handle.can_lock(), &self.0.locked_response) {
+                // Locked by another session, respond with an error instead of waiting
+                (
+                    Err(SharedLockError::LockedByShared | SharedLockError::LockedByExclusive),
+                    Some(locked_response),
+                ) => {
+                    log::debug!("{:?} device locked", peer);
+                    Some(AsyncOutput::from(locked_response.clone()))
+                }
+                _ => {
+                    let device = handle
+                        .async_lock()
+                        .await
+                        .map_err(|err| io::Error::other(format!("Failed to lock: {err:?}")))?;
+                    AsyncDevice::execute_async(device, cmd.clone()).await
+                }
             };
 
+            #[cfg(feature = "fault-injection")]
+            if resp.is_some() {
+                responses = responses.wrapping_add(1);
+                if let Some(delay) = self.0.faults.delay {
+                    task::sleep(delay).await;
+                }
+                match self.0.faults.fault(responses) {
+                    Fault::None => {}
+                    Fault::Drop => {
+                        log::debug!("{:?} dropping response (injected)", peer);
+                        continue;
+                    }
+                    // Socket has no protocol errors, drop connection
+                    Fault::Error => {
+                        log::debug!("{:?} closing connection (injected)", peer);
+                        return Err(io::ErrorKind::ConnectionAborted.into());
+                    }
+                }
+            }
+
             // Write back
             if let Some(mut data) = resp {
-                data.push(self.0.write_termination);
-                log::trace!("{:?} write {} bytes", peer, data.len());
-                writer.write_all(&data).await?;
+                let termination = self.0.append_write_termination as usize;
+                log::trace!("{:?} write {} bytes", peer, data.len + termination);
+                while let Some(chunk) = data.chunks.next().await {
+                    writer.write_all(&chunk).await?;
+                }
+                if self.0.append_write_termination {
+                    writer.write_all(&[self.0.write_termination]).await?;
+                }
                 //writer.flush().await?;
             }
-
-            // Clear until next message
-            cmd.clear();
         }
 
         Ok(())
     }
 }
 
+/// Command termination used when reading from a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// No termination, the whole stream up to EOF is one command
+    None,
+    /// NUL (`\0`)
+    Null,
+    /// LF (`\n`)
+    Lf,
+    /// CR (`\r`)
+    Cr,
+    /// LF, optionally preceded by a CR (`\r\n`)
+    CrLf,
+    /// Any other ASCII control code
+    Char(u8),
+}
+
+impl Termination {
+    /// Byte which ends a command, if any
+    pub fn byte(&self) -> Option<u8> {
+        match self {
+            Termination::None => None,
+            Termination::Null => Some(b'\0'),
+            Termination::Lf | Termination::CrLf => Some(b'\n'),
+            Termination::Cr => Some(b'\r'),
+            Termination::Char(c) => Some(*c),
+        }
+    }
+
+    /// Remove termination from the end of a command
+    fn strip(&self, cmd: &mut Vec<u8>) {
+        if let Some(term) = self.byte() {
+            if cmd.last() == Some(&term) {
+                cmd.pop();
+                if *self == Termination::CrLf && cmd.last() == Some(&b'\r') {
+                    cmd.pop();
+                }
+            }
+        }
+    }
+}
+
+impl From<u8> for Termination {
+    fn from(c: u8) -> Self {
+        match c {
+            b'\0' => Termination::Null,
+            b'\n' => Termination::Lf,
+            b'\r' => Termination::Cr,
+            c => Termination::Char(c),
+        }
+    }
+}
+
+/// An error returned by an invalid [ServerConfig] setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// Termination character is not a ASCII control code
+    InvalidTermination(u8),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidTermination(c) => {
+                write!(f, "Invalid termination character: {c:#04x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 /// Socket server configuration builder
 ///
 pub struct ServerConfig {
     read_buffer: usize,
     limit: usize,
-    read_termination: u8,
+    read_termination: Termination,
     write_termination: u8,
+    append_write_termination: bool,
+    locked_response: Option<Vec<u8>>,
+    #[cfg(feature = "fault-injection")]
+    faults: FaultInjection,
 }
 
 impl Default for ServerConfig {
@@ -174,20 +315,41 @@ impl Default for ServerConfig {
         ServerConfig {
             read_buffer: 512 * 1024,
             limit: 10,
-            read_termination: b'\n',
+            read_termination: Termination::Lf,
             write_termination: b'\n',
+            append_write_termination: true,
+            locked_response: None,
+            #[cfg(feature = "fault-injection")]
+            faults: FaultInjection::default(),
         }
     }
 }
 
 impl ServerConfig {
+    /// Create a new config using `termination_char` for both reads and writes.
+    ///
+    /// If termination character is not a ASCII control code (e.g. LF, CR, etc) a warning is logged
+    /// and the default termination is used instead. Use [ServerConfig::try_new] to get an error.
     pub fn new(read_buffer: usize, termination_char: u8) -> Self {
+        Self::try_new(read_buffer, termination_char).unwrap_or_else(|err| {
+            log::warn!("{}, using default termination", err);
+            ServerConfig {
+                read_buffer,
+                ..Default::default()
+            }
+        })
+    }
+
+    /// Create a new config using `termination_char` for both reads and writes.
+    ///
+    /// Returns an error if termination character is not a ASCII control code (e.g. LF, CR, etc).
+    pub fn try_new(read_buffer: usize, termination_char: u8) -> Result<Self, ConfigError> {
         ServerConfig {
             read_buffer,
-            read_termination: termination_char,
-            write_termination: termination_char,
             ..Default::default()
         }
+        .try_read_termination(termination_char)?
+        .try_write_termination(termination_char)
     }
 
     /// Set the read buffer size
@@ -201,26 +363,46 @@ impl ServerConfig {
 
     /// Set the termination character for reads.
     ///
-    /// # Panics
+    /// Returns an error if termination character is not a ASCII control code (e.g. LF, CR, etc).
+    pub fn try_read_termination(self, read_termination: u8) -> Result<Self, ConfigError> {
+        self.read_termination(read_termination.into())
+    }
+
+    /// Set the termination mode for reads.
     ///
-    /// Panics if termination character is not a ASCII control code (e.g. LF, CR, etc).
-    pub fn read_termination(self, read_termination: u8) -> Self {
-        debug_assert!(read_termination.is_ascii_control());
-        Self {
+    /// Returns an error if a [Termination::Char] is not a ASCII control code (e.g. LF, CR, etc).
+    pub fn read_termination(self, read_termination: Termination) -> Result<Self, ConfigError> {
+        if let Termination::Char(c) = read_termination {
+            if !c.is_ascii_control() {
+                return Err(ConfigError::InvalidTermination(c));
+            }
+        }
+        Ok(Self {
             read_termination,
             ..self
-        }
+        })
     }
 
     /// Set the termination character for writes.
     ///
-    /// # Panics
+    /// Returns an error if termination character is not a ASCII control code (e.g. LF, CR, etc).
+    pub fn try_write_termination(self, write_termination: u8) -> Result<Self, ConfigError> {
+        if !write_termination.is_ascii_control() {
+            return Err(ConfigError::InvalidTermination(write_termination));
+        }
+        Ok(Self {
+            write_termination,
+            ..self
+        })
+    }
+
+    /// Append the write termination character to responses (default).
     ///
-    /// Panics if termination character is not a ASCII control code (e.g. LF, CR, etc).
-    pub fn write_termination(self, write_termination: u8) -> Self {
-        debug_assert!(write_termination.is_ascii_control());
+    /// Disable if the device terminates its own responses, e.g. when returning binary
+    /// definite length blocks which must not be followed by an extra byte.
+    pub fn append_write_termination(self, append_write_termination: bool) -> Self {
         Self {
-            write_termination,
+            append_write_termination,
             ..self
         }
     }
@@ -231,7 +413,83 @@ impl ServerConfig {
         Self { limit, ..self }
     }
 
+    /// Respond with `response` (e.g. `-360,"Device locked"`) instead of waiting when another
+    /// session holds a lock on the device.
+    ///
+    pub fn locked_response(self, response: &[u8]) -> Self {
+        Self {
+            locked_response: Some(response.to_vec()),
+            ..self
+        }
+    }
+
+    /// Inject faults into responses. Only intended for testing clients.
+    ///
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injection(self, faults: FaultInjection) -> Self {
+        Self { faults, ..self }
+    }
+
     pub fn build(self) -> Arc<Server> {
         Arc::new(Server(self))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, ServerConfig, Termination};
+
+    #[test]
+    fn test_termination() {
+        assert!(ServerConfig::default().try_read_termination(b'\r').is_ok());
+        assert!(ServerConfig::default().try_write_termination(b'\0').is_ok());
+        assert!(ServerConfig::try_new(1024, b'\n').is_ok());
+
+        // Printable characters are rejected in all build profiles
+        assert!(matches!(
+            ServerConfig::default().try_read_termination(b'a'),
+            Err(ConfigError::InvalidTermination(b'a'))
+        ));
+        assert!(matches!(
+            ServerConfig::default().try_write_termination(b';'),
+            Err(ConfigError::InvalidTermination(b';'))
+        ));
+        assert!(matches!(
+            ServerConfig::try_new(1024, b' '),
+            Err(ConfigError::InvalidTermination(b' '))
+        ));
+
+        // Infallible constructor falls back to the default termination
+        let config = ServerConfig::new(1024, b' ');
+        assert_eq!(config.read_buffer, 1024);
+        assert_eq!(config.read_termination, Termination::Lf);
+        assert_eq!(config.write_termination, b'\n');
+        assert_eq!(
+            ServerConfig::new(1024, b'\r').read_termination,
+            Termination::Cr
+        );
+    }
+
+    #[test]
+    fn test_termination_mode() {
+        assert_eq!(Termination::from(b'\n'), Termination::Lf);
+        assert_eq!(Termination::from(0x04), Termination::Char(0x04));
+        assert!(matches!(
+            ServerConfig::default().read_termination(Termination::Char(b'x')),
+            Err(ConfigError::InvalidTermination(b'x'))
+        ));
+
+        let strip = |term: Termination, cmd: &[u8]| {
+            let mut cmd = cmd.to_vec();
+            term.strip(&mut cmd);
+            cmd
+        };
+        assert_eq!(strip(Termination::Lf, b"*IDN?\n"), b"*IDN?");
+        assert_eq!(strip(Termination::Lf, b"*IDN?\r\n"), b"*IDN?\r");
+        assert_eq!(strip(Termination::CrLf, b"*IDN?\r\n"), b"*IDN?");
+        assert_eq!(strip(Termination::CrLf, b"*IDN?\n"), b"*IDN?");
+        assert_eq!(strip(Termination::None, b"*IDN?\n"), b"*IDN?\n");
+        // Unterminated command at EOF
+        assert_eq!(strip(Termination::Lf, b"*IDN?"), b"*IDN?");
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use async_std::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    os::unix::net::UnixStream,
+    task,
+};
+use futures::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use lxi_device::{lock::SharedLock, util::SimpleDevice};
+use lxi_socket::server::ServerConfig;
+
+/// Send `*IDN?` and return the response
+async fn query_idn<IO>(stream: IO) -> Vec<u8>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+
+    writer.write_all(b"*IDN?\n").await.unwrap();
+    reader.read_until(b'\n', &mut buf).await.unwrap();
+    buf
+}
+
+/// Connect, retrying until the server is listening
+async fn retry<T, F, Fut>(connect: F) -> T
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = std::io::Result<T>>,
+{
+    for _ in 0..50 {
+        if let Ok(stream) = connect().await {
+            return stream;
+        }
+        task::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Server not listening")
+}
+
+#[async_std::test]
+async fn tcp_and_unix() {
+    let device = SimpleDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().build();
+
+    // Find a free port
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    task::spawn(
+        server
+            .clone()
+            .accept(addr, shared_lock.clone(), device.clone()),
+    );
+
+    let path = std::env::temp_dir().join(format!("lxi-socket-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    task::spawn(server.accept_unix(path.clone(), shared_lock, device));
+
+    let tcp = query_idn(retry(|| TcpStream::connect(addr)).await).await;
+    let unix = query_idn(retry(|| UnixStream::connect(&path)).await).await;
+    let _ = std::fs::remove_file(&path);
+
+    assert!(tcp.starts_with(b"Cyberdyne systems"));
+    assert_eq!(tcp, unix);
+}
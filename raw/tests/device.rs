@@ -1,12 +1,19 @@
-use std::sync::Arc;
+use std::{
+    net::Shutdown,
+    panic::AssertUnwindSafe,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
 
 use async_std::{io::BufReader, os::unix::net::UnixStream};
-use futures::{join, lock::Mutex, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use futures::{join, lock::Mutex, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, FutureExt};
 use lxi_device::{
-    lock::{SharedLock, SpinMutex},
+    lock::{LockHandle, SharedLock, SpinMutex},
+    trigger::Source,
     util::EchoDevice,
+    AsyncDevice, Blocking, Device, DeviceError, ExecuteOutput,
 };
-use lxi_socket::server::ServerConfig;
+use lxi_socket::server::{ServerConfig, Termination};
 
 async fn run_echo_server(
     stream: UnixStream,
@@ -50,3 +57,349 @@ async fn echo() {
         client_fut
     );
 }
+
+#[async_std::test]
+async fn crlf_termination() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default()
+        .read_termination(Termination::CrLf)
+        .unwrap()
+        .build();
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        client_write.write_all(b"first\r\nsecond\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"first\nsecond\n");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "crlf"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn no_termination() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default()
+        .read_termination(Termination::None)
+        .unwrap()
+        .build();
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let mut client_stream = client_stream;
+        let mut buf = Vec::new();
+
+        // Whole stream up to EOF is one command
+        client_stream.write_all(b"first\nsecond\n").await.unwrap();
+        client_stream.shutdown(Shutdown::Write).unwrap();
+        client_stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"first\nsecond\n\n");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "none"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn locked_wait() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().build();
+
+    // Other session holds an exclusive lock
+    let mut other = LockHandle::new(shared_lock.clone(), device.clone());
+    other.try_acquire_exclusive().unwrap();
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        client_write.write_all(b"test\n").await.unwrap();
+
+        // Command waits for the lock to be released
+        let res = async_std::io::timeout(
+            Duration::from_millis(100),
+            client_read.read_until(b'\n', &mut buf),
+        )
+        .await;
+        assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+
+        other.force_release();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"test\n");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "wait"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn locked_response() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default()
+        .locked_response(b"-360,\"Device locked\"")
+        .build();
+
+    // Other session holds an exclusive lock
+    let mut other = LockHandle::new(shared_lock.clone(), device.clone());
+    other.try_acquire_exclusive().unwrap();
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        client_write.write_all(b"test\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"-360,\"Device locked\"\n");
+
+        // Executed normally once released
+        other.force_release();
+        buf.clear();
+        client_write.write_all(b"test\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"test\n");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "locked"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn no_write_termination() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default()
+        .read_termination(Termination::Null)
+        .unwrap()
+        .append_write_termination(false)
+        .build();
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (mut client_read, mut client_write) = client_stream.split();
+
+        // Definite length block followed by a second response, no bytes in between
+        client_write.write_all(b"#15ab\ncd\0x\0").await.unwrap();
+        let mut buf = [0u8; 9];
+        client_read.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"#15ab\ncdx");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "block"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+/// Echo device which panics on `PANIC`
+struct PanicDevice;
+
+impl Device for PanicDevice {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        if cmd == b"PANIC" {
+            panic!("Device panicked");
+        }
+        Some(ExecuteOutput::Borrowed(cmd))
+    }
+
+    fn get_status(&mut self) -> Result<u8, DeviceError> {
+        Ok(0)
+    }
+
+    fn trigger(&mut self, _: Source) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+impl AsyncDevice for PanicDevice {}
+
+/// Echo device which blocks until signalled before responding
+struct SlowDevice(mpsc::Receiver<()>);
+
+impl Device for SlowDevice {
+    fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        self.0.recv_timeout(Duration::from_secs(1)).ok()?;
+        Some(ExecuteOutput::Borrowed(cmd))
+    }
+
+    fn get_status(&mut self) -> Result<u8, DeviceError> {
+        Ok(0)
+    }
+
+    fn trigger(&mut self, _: Source) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+#[async_std::test]
+async fn blocking_execute() {
+    let (tx, rx) = mpsc::channel();
+    let device = Blocking::new_arc(SlowDevice(rx));
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().read_buffer(16 * 1024).build();
+
+    // Client runs on the same task as the server while the device blocks
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        client_write.write_all(b"first\nsecond\n").await.unwrap();
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        tx.send(()).unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        tx.send(()).unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"first\nsecond\n");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "slow"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+/// Device which responds with 1000 small chunks
+struct ChunkDevice;
+
+impl Device for ChunkDevice {
+    fn execute<'a>(&mut self, _cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+        Some(ExecuteOutput::Stream {
+            len: 1000 * 4,
+            chunks: Box::new((0..1000).map(|i| format!("{i:03},").into_bytes())),
+        })
+    }
+
+    fn get_status(&mut self) -> Result<u8, DeviceError> {
+        Ok(0)
+    }
+
+    fn trigger(&mut self, _: Source) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn set_remote(&mut self, _remote: bool) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+impl AsyncDevice for ChunkDevice {}
+
+#[async_std::test]
+async fn chunked_response() {
+    let device = Arc::new(Mutex::new(ChunkDevice));
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().read_buffer(16 * 1024).build();
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        client_write.write_all(b"DATA?\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        drop(client_write);
+
+        // All chunks in order, termination only at the end
+        let expected: Vec<u8> = (0..1000)
+            .flat_map(|i| format!("{i:03},").into_bytes())
+            .chain([b'\n'])
+            .collect();
+        assert_eq!(buf, expected);
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "chunks"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn device_panic() {
+    let device = Arc::new(Mutex::new(PanicDevice));
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().read_buffer(16 * 1024).build();
+
+    // Session panics while holding the device
+    let (mut client_stream, server_stream) = UnixStream::pair().unwrap();
+    client_stream.write_all(b"PANIC\n").await.unwrap();
+    let (reader, writer) = server_stream.split();
+    let res = AssertUnwindSafe(server.clone().process_client(
+        reader,
+        writer,
+        shared_lock.clone(),
+        device.clone(),
+        "panic",
+    ))
+    .catch_unwind()
+    .await;
+    assert!(res.is_err());
+
+    // Neither the device mutex nor the shared lock are left locked (they do not poison)
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        client_write.write_all(b"test\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"test\n");
+    };
+    let (reader, writer) = server_stream.split();
+    let (ret, _) = join!(
+        server.process_client(reader, writer, shared_lock, device, "recovered"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
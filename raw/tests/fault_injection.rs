@@ -0,0 +1,85 @@
+#![cfg(feature = "fault-injection")]
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_std::{io::BufReader, os::unix::net::UnixStream};
+use futures::{join, lock::Mutex, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use lxi_device::{
+    fault::FaultInjection,
+    lock::{SharedLock, SpinMutex},
+    util::EchoDevice,
+};
+use lxi_socket::server::ServerConfig;
+
+async fn run_echo_server(
+    stream: UnixStream,
+    shared_lock: Arc<SpinMutex<SharedLock>>,
+    device: Arc<Mutex<EchoDevice>>,
+    faults: FaultInjection,
+) {
+    let peer = stream.peer_addr().unwrap();
+    let (reader, writer) = stream.split();
+    let server = ServerConfig::default()
+        .read_buffer(16 * 1024)
+        .fault_injection(faults)
+        .build();
+    let ret = server
+        .process_client(reader, writer, shared_lock, device, peer)
+        .await;
+    println!("Server exit {:?}", ret);
+}
+
+#[async_std::test]
+async fn delay() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let faults = FaultInjection::default().delay(Duration::from_millis(200));
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        let start = Instant::now();
+        client_write.write_all(b"test\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"test\n");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    };
+
+    join!(
+        run_echo_server(server_stream, shared_lock, device, faults),
+        client_fut
+    );
+}
+
+#[async_std::test]
+async fn drop_response() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let faults = FaultInjection::default().drop_every(2);
+
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+
+    let client_fut = async move {
+        let (client_read, mut client_write) = client_stream.split();
+        let mut client_read = BufReader::new(client_read);
+        let mut buf = Vec::new();
+
+        // Second response is dropped
+        client_write.write_all(b"one\ntwo\nthree\n").await.unwrap();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"one\n");
+        buf.clear();
+        client_read.read_until(b'\n', &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), b"three\n");
+    };
+
+    join!(
+        run_echo_server(server_stream, shared_lock, device, faults),
+        client_fut
+    );
+}
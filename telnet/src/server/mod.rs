@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::time::Duration;
 
@@ -17,7 +18,7 @@ use libtelnet_rs::{telnet::op_option as options, Parser};
 use lxi_device::lock::SpinMutex;
 use lxi_device::{
     lock::{LockHandle, SharedLock},
-    Device,
+    AsyncDevice,
 };
 
 pub struct Server(ServerConfig);
@@ -31,7 +32,7 @@ impl Server {
         device: Arc<Mutex<DEV>>,
     ) -> io::Result<()>
     where
-        DEV: Device + Send + 'static,
+        DEV: AsyncDevice,
     {
         let listener = TcpListener::bind(addr).await?;
         let mut incoming = listener
@@ -64,10 +65,10 @@ impl Server {
         mut stream: IO,
         shared_lock: Arc<SpinMutex<SharedLock>>,
         device: Arc<Mutex<DEV>>,
-        _peer: SA,
+        peer: SA,
     ) -> io::Result<()>
     where
-        DEV: Device + Send,
+        DEV: AsyncDevice,
         IO: Read + Write + Unpin,
         SA: Debug,
     {
@@ -80,8 +81,18 @@ impl Server {
 
         let mut buf = [0u8; 128];
         let mut cmd = Vec::with_capacity(self.0.read_buffer);
+        // Set when current line exceeded max_command_len, rest of line is discarded
+        let mut overflow = false;
+        let mut escape = Escape::None;
+        let mut history = History::new(self.0.history_size);
 
-        let prompt = Parser::escape_iac(&b"SCPI> "[..]);
+        let prompt = Parser::escape_iac(self.0.prompt.clone());
+
+        if let Some(banner) = &self.0.banner {
+            stream
+                .write_all(&Parser::escape_iac(banner.clone()))
+                .await?;
+        }
 
         loop {
             stream.write_all(&prompt).await?;
@@ -92,6 +103,10 @@ impl Server {
 
             // Read a line from stream.
             let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                log::info!("{:?} disconnected", peer);
+                return Ok(());
+            }
 
             let events = instance.receive(&buf[..n]);
             for event in events {
@@ -109,28 +124,111 @@ impl Server {
                     TelnetEvents::Subnegotiation(_sub) => {}
                     TelnetEvents::DataReceive(data) => {
                         for b in data {
+                            // ANSI escape sequences, up/down recalls history
+                            match (escape, b) {
+                                (Escape::None, 0x1b) => {
+                                    escape = Escape::Esc;
+                                    continue;
+                                }
+                                (Escape::None, _) => {}
+                                (Escape::Esc, b'[') => {
+                                    escape = Escape::Csi;
+                                    continue;
+                                }
+                                (Escape::Esc, _) => {
+                                    escape = Escape::None;
+                                    continue;
+                                }
+                                (Escape::Csi, 0x40..=0x7e) => {
+                                    escape = Escape::None;
+                                    let entry = match b {
+                                        b'A' => history.prev(),
+                                        b'B' => history.next(),
+                                        _ => None,
+                                    };
+                                    if let Some(entry) = entry {
+                                        cmd.clear();
+                                        cmd.extend_from_slice(entry);
+                                        overflow = false;
+
+                                        // Redraw line
+                                        if instance.options.get_option(options::ECHO).local_state {
+                                            stream.write_all(b"\r\x1b[K").await?;
+                                            stream.write_all(&prompt).await?;
+                                            stream
+                                                .write_all(&Parser::escape_iac(cmd.clone()))
+                                                .await?;
+                                        }
+                                    }
+                                    continue;
+                                }
+                                // Parameter/intermediate bytes
+                                (Escape::Csi, _) => continue,
+                            }
+
+                            let erase = b == 0x08 /* BS */ || b == 0x7f /* DEL */;
+
                             // Echo back if enabled
                             if instance.options.get_option(options::ECHO).local_state {
-                                stream.write_all(&[b]).await?;
+                                if erase {
+                                    stream.write_all(b"\x08 \x08").await?;
+                                } else {
+                                    stream.write_all(&[b]).await?;
+                                }
                             }
 
-                            if b == b'\n' {
-                                // Remove \r
+                            if erase {
                                 cmd.pop();
-                                // Lock device and execute
-                                let resp = {
-                                    let mut device = handle.async_lock().await.unwrap();
-                                    device.execute(&cmd)
-                                };
+                            } else if b == b'\n' && overflow {
+                                log::warn!(
+                                    "Command exceeded {} bytes, discarded",
+                                    self.0.max_command_len
+                                );
+                                stream.write_all(b"ERROR: Command too long\r\n").await?;
+                                overflow = false;
                                 cmd.clear();
+                            } else if b == b'\n' {
+                                // Remove \r
+                                if cmd.last() == Some(&b'\r') {
+                                    cmd.pop();
+                                }
+                                {
+                                    // Lock device and execute
+                                    let resp = {
+                                        let device = handle.async_lock().await.unwrap();
+                                        AsyncDevice::execute_async(device, cmd.clone()).await
+                                    };
+
+                                    // Send back response if any
+                                    if let Some(mut data) = resp {
+                                        if data.len > self.0.max_response_len {
+                                            log::warn!(
+                                                "Response truncated from {} to {} bytes",
+                                                data.len,
+                                                self.0.max_response_len
+                                            );
+                                        }
 
-                                // Send back response if any
-                                if let Some(data) = resp {
-                                    let to_send = Parser::escape_iac(data);
-                                    stream.write_all(&to_send).await?;
-                                    stream.write_all(b"\r\n").await?;
+                                        let mut remaining = self.0.max_response_len;
+                                        while let Some(chunk) = data.chunks.next().await {
+                                            let n = chunk.len().min(remaining);
+                                            let to_send = Parser::escape_iac(chunk[..n].to_vec());
+                                            stream.write_all(&to_send).await?;
+                                            remaining -= n;
+                                            if remaining == 0 {
+                                                break;
+                                            }
+                                        }
+                                        stream.write_all(b"\r\n").await?;
+                                    }
                                 }
-                            } else {
+                                history.push(&cmd);
+                                cmd.clear();
+                            } else if cmd.len() >= self.0.max_command_len {
+                                // Drop data until end of line
+                                overflow = true;
+                                cmd.clear();
+                            } else if !overflow {
                                 cmd.push(b);
                             }
                         }
@@ -145,12 +243,84 @@ impl Server {
     }
 }
 
+/// State of ANSI escape sequence parsing
+#[derive(Debug, Clone, Copy)]
+enum Escape {
+    None,
+    /// Received ESC
+    Esc,
+    /// Received ESC [
+    Csi,
+}
+
+/// Bounded command history of a session
+struct History {
+    entries: VecDeque<Vec<u8>>,
+    size: usize,
+    /// Entry currently recalled, `None` when editing a new line
+    pos: Option<usize>,
+}
+
+impl History {
+    fn new(size: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(size),
+            size,
+            pos: None,
+        }
+    }
+
+    /// Add an executed command, empty commands are ignored
+    fn push(&mut self, cmd: &[u8]) {
+        self.pos = None;
+        if cmd.is_empty() || self.size == 0 {
+            return;
+        }
+        if self.entries.len() >= self.size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(cmd.to_vec());
+    }
+
+    /// Recall the previous (older) command
+    fn prev(&mut self) -> Option<&[u8]> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let pos = self
+            .pos
+            .map_or(self.entries.len() - 1, |pos| pos.saturating_sub(1));
+        self.pos = Some(pos);
+        self.entries.get(pos).map(Vec::as_slice)
+    }
+
+    /// Recall the next (newer) command, or an empty line after the newest
+    fn next(&mut self) -> Option<&[u8]> {
+        match self.pos {
+            Some(pos) if pos + 1 < self.entries.len() => {
+                self.pos = Some(pos + 1);
+                self.entries.get(pos + 1).map(Vec::as_slice)
+            }
+            Some(_) => {
+                self.pos = None;
+                Some(&[])
+            }
+            None => None,
+        }
+    }
+}
+
 /// Socket server configuration builder
 ///
 #[cfg_attr(feature = "serde", derive(Deserializer, Serializer))]
 pub struct ServerConfig {
     read_buffer: usize,
     limit: usize,
+    max_command_len: usize,
+    max_response_len: usize,
+    prompt: Vec<u8>,
+    banner: Option<Vec<u8>>,
+    history_size: usize,
 }
 
 impl Default for ServerConfig {
@@ -158,6 +328,11 @@ impl Default for ServerConfig {
         ServerConfig {
             read_buffer: 512 * 1024,
             limit: 10,
+            max_command_len: 512 * 1024,
+            max_response_len: 16 * 1024 * 1024,
+            prompt: b"SCPI> ".to_vec(),
+            banner: None,
+            history_size: 32,
         }
     }
 }
@@ -185,6 +360,54 @@ impl ServerConfig {
         Self { limit, ..self }
     }
 
+    /// Set the maximum length of a command line.
+    ///
+    /// Longer lines are discarded and an error message is sent to the client.
+    pub fn max_command_len(self, max_command_len: usize) -> Self {
+        Self {
+            max_command_len,
+            ..self
+        }
+    }
+
+    /// Set the maximum length of a response.
+    ///
+    /// Longer responses are truncated.
+    pub fn max_response_len(self, max_response_len: usize) -> Self {
+        Self {
+            max_response_len,
+            ..self
+        }
+    }
+
+    /// Set the prompt written before each command.
+    ///
+    pub fn prompt(self, prompt: &[u8]) -> Self {
+        Self {
+            prompt: prompt.to_vec(),
+            ..self
+        }
+    }
+
+    /// Set a banner written once when a client connects.
+    ///
+    pub fn banner(self, banner: &[u8]) -> Self {
+        Self {
+            banner: Some(banner.to_vec()),
+            ..self
+        }
+    }
+
+    /// Set the number of commands kept in the history of each session.
+    ///
+    /// Previous commands can be recalled using the up/down arrow keys. Zero disables history.
+    pub fn history_size(self, history_size: usize) -> Self {
+        Self {
+            history_size,
+            ..self
+        }
+    }
+
     /// Finishes and reurns the server
     pub fn build(self) -> Arc<Server> {
         Arc::new(Server(self))
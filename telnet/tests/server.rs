@@ -0,0 +1,146 @@
+use async_std::os::unix::net::UnixStream;
+use futures::{join, AsyncReadExt, AsyncWriteExt};
+use lxi_device::{lock::SharedLock, util::EchoDevice};
+use lxi_telnet::server::ServerConfig;
+
+#[async_std::test]
+async fn banner_and_prompt() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default()
+        .banner(b"Welcome to DMM #2\r\n")
+        .prompt(b"DMM2> ")
+        .build();
+
+    let (mut client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        // Banner is sent before the first prompt (followed by a go ahead)
+        let mut buf = [0u8; 27];
+        client_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"Welcome to DMM #2\r\nDMM2> \xff\x03");
+
+        // Prompt again after each command
+        client_stream.write_all(b"test\r\n").await.unwrap();
+        let mut buf = [0u8; 14];
+        client_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"test\r\nDMM2> \xff\x03");
+    };
+
+    let (ret, _) = join!(
+        server.process_client(server_stream, shared_lock, device, "banner"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn erase_character() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().prompt(b"").build();
+
+    let (mut client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let mut buf = [0u8; 2];
+        client_stream.read_exact(&mut buf).await.unwrap();
+
+        // DEL and BS both erase the previous character
+        client_stream.write_all(b"abc\x7f\x7fd\n").await.unwrap();
+        let mut buf = [0u8; 6];
+        client_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ad\r\n\xff\x03");
+
+        client_stream.write_all(b"xy\x08z\r\n").await.unwrap();
+        let mut buf = [0u8; 6];
+        client_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"xz\r\n\xff\x03");
+    };
+
+    let (ret, _) = join!(
+        server.process_client(server_stream, shared_lock, device, "erase"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+#[async_std::test]
+async fn history() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default().prompt(b"").build();
+
+    let (mut client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let mut buf = [0u8; 2];
+        client_stream.read_exact(&mut buf).await.unwrap();
+
+        for cmd in [&b"first\n"[..], b"second\n"] {
+            client_stream.write_all(cmd).await.unwrap();
+            let mut buf = vec![0u8; cmd.len() + 3];
+            client_stream.read_exact(&mut buf).await.unwrap();
+        }
+
+        // Up twice recalls the older command
+        client_stream.write_all(b"\x1b[A\x1b[A\n").await.unwrap();
+        let mut buf = [0u8; 9];
+        client_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"first\r\n\xff\x03");
+
+        // Up then down returns to an empty line
+        client_stream.write_all(b"\x1b[A\x1b[Bnew\n").await.unwrap();
+        let mut buf = [0u8; 7];
+        client_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"new\r\n\xff\x03");
+    };
+
+    let (ret, _) = join!(
+        server.process_client(server_stream, shared_lock, device, "history"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
+
+/// Read until `end` has been received
+async fn read_until_end(stream: &mut UnixStream, end: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    while !buf.ends_with(end) {
+        let mut b = [0u8; 1];
+        stream.read_exact(&mut b).await.unwrap();
+        buf.push(b[0]);
+    }
+    buf
+}
+
+#[async_std::test]
+async fn long_command() {
+    let device = EchoDevice::new_arc();
+    let shared_lock = SharedLock::new();
+    let server = ServerConfig::default()
+        .prompt(b"")
+        .max_command_len(16)
+        .build();
+
+    let (mut client_stream, server_stream) = UnixStream::pair().unwrap();
+    let client_fut = async move {
+        let mut buf = [0u8; 2];
+        client_stream.read_exact(&mut buf).await.unwrap();
+
+        // Line is discarded
+        client_stream.write_all(&[b'A'; 64]).await.unwrap();
+        client_stream.write_all(b"\r\n").await.unwrap();
+        let buf = read_until_end(&mut client_stream, b"\r\n\xff\x03").await;
+        assert!(buf.ends_with(b"ERROR: Command too long\r\n\xff\x03"));
+        assert!(!buf.contains(&b'A'));
+
+        // Session continues
+        client_stream.write_all(b"test\r\n").await.unwrap();
+        let buf = read_until_end(&mut client_stream, b"\r\n\xff\x03").await;
+        assert_eq!(buf, b"test\r\n\xff\x03");
+    };
+
+    let (ret, _) = join!(
+        server.process_client(server_stream, shared_lock, device, "long"),
+        client_fut
+    );
+    assert!(ret.is_ok());
+}
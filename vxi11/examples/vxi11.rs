@@ -12,10 +12,7 @@ use lxi_device::{
     util::{EchoDevice, SimpleDevice},
     Device,
 };
-use lxi_vxi11::{
-    client::portmapper::prelude::*,
-    server::{portmapper::prelude::*, vxi11::prelude::*},
-};
+use lxi_vxi11::{client::portmapper::prelude::*, server::vxi11::prelude::*};
 
 use clap::Parser;
 
@@ -82,12 +79,16 @@ async fn main() -> io::Result<()> {
         None => pending().left_future(),
     };
 
-    let (vxi11_core, vxi11_async) = VxiServerBuilder::new()
+    let mut builder = VxiServerBuilder::new()
         .core_port(core_listener.local_addr()?.port())
         .async_port(async_listener.local_addr()?.port())
         .device("inst0".to_string(), device0, shared_lock0)
-        .device("inst1".to_string(), device1, shared_lock1)
-        .build(srq);
+        .device("inst1".to_string(), device1, shared_lock1);
+    if !args.register {
+        log::info!("Running portmap ...");
+        builder = builder.serve_portmap((Ipv4Addr::UNSPECIFIED, PORTMAPPER_PORT).into());
+    }
+    let (vxi11_core, vxi11_async) = builder.build(srq);
 
     if args.register {
         let mut portmap =
@@ -114,29 +115,6 @@ async fn main() -> io::Result<()> {
             ))
             .await
             .expect("Failed to register async channel");
-    } else {
-        let portmap = StaticPortMap::new([
-            Mapping::new(
-                DEVICE_CORE, // VXI-11 CORE
-                DEVICE_CORE_VERSION,
-                PORTMAPPER_PROT_TCP,
-                core_listener.local_addr()?.port() as u32,
-            ),
-            Mapping::new(
-                DEVICE_ASYNC, // VXI-11 ASYNC
-                DEVICE_ASYNC_VERSION,
-                PORTMAPPER_PROT_TCP,
-                async_listener.local_addr()?.port() as u32,
-            ),
-        ]);
-
-        log::info!("Running portmap ...");
-        spawn(async move {
-            portmap
-                .bind((Ipv4Addr::UNSPECIFIED, PORTMAPPER_PORT))
-                .await
-                .expect("Failed to run portmap")
-        });
     }
 
     let core_handle = spawn(vxi11_core.serve(core_listener));
@@ -1,13 +1,14 @@
 use std::io;
+use std::net::SocketAddr;
 
 use async_std::net::{TcpStream, ToSocketAddrs, UdpSocket};
 
 use crate::common::{
     onc_rpc::prelude::*,
     portmapper::{
-        xdr::{CallResult, Callit, Mapping},
-        PMAPPROC_CALLIT, PMAPPROC_GETPORT, PMAPPROC_NULL, PMAPPROC_SET, PMAPPROC_UNSET,
-        PORTMAPPER_PROG, PORTMAPPER_VERS,
+        xdr::{CallResult, Callit, Mapping, MappingList},
+        PMAPPROC_CALLIT, PMAPPROC_DUMP, PMAPPROC_GETPORT, PMAPPROC_NULL, PMAPPROC_SET,
+        PMAPPROC_UNSET, PORTMAPPER_PROG, PORTMAPPER_VERS,
     },
     xdr::prelude::*,
 };
@@ -33,7 +34,17 @@ impl PortMapperClient {
     }
 
     pub async fn connect_udp(addrs: impl ToSocketAddrs) -> io::Result<Self> {
-        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        Self::connect_udp_from("127.0.0.1:0", addrs).await
+    }
+
+    /// Connect using UDP from a specific local address, e.g. to select interface on a multi-homed host.
+    ///
+    /// Note: Not supported for TCP, async-std cannot bind a TCP socket before connecting.
+    pub async fn connect_udp_from(
+        local_addr: impl ToSocketAddrs,
+        addrs: impl ToSocketAddrs,
+    ) -> io::Result<Self> {
+        let sock = UdpSocket::bind(local_addr).await?;
         sock.connect(addrs).await?;
         Ok(Self(RpcClient::Udp(UdpRpcClient::new(
             PORTMAPPER_PROG,
@@ -42,6 +53,11 @@ impl PortMapperClient {
         ))))
     }
 
+    /// Local address of the client socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+
     pub async fn register(&mut self, mapping: Mapping) -> Result<(), RpcError> {
         let res = self.unset(mapping).await?;
         if !res {
@@ -70,6 +86,12 @@ impl PortMapperClient {
         self.0.call(PMAPPROC_GETPORT, mapping).await
     }
 
+    /// List all registered mappings
+    pub async fn dump(&mut self) -> Result<Vec<Mapping>, RpcError> {
+        let list: MappingList = self.0.call(PMAPPROC_DUMP, ()).await?;
+        Ok(list.0)
+    }
+
     pub async fn callit<ARGS, RET>(
         &mut self,
         prog: u32,
@@ -2,14 +2,16 @@
 
 use std::{
     io::{self, Cursor, Error, ErrorKind, Write},
+    net::SocketAddr,
     sync::Arc,
 };
 
 use async_std::net::{TcpStream, UdpSocket};
 use async_trait::async_trait;
 
-use self::record::{read_record, write_record};
+use self::record::{read_next_record, read_record, write_record};
 use futures::{AsyncRead, AsyncWrite};
+use lxi_device::stats::MessageStats;
 
 mod record;
 mod xdr;
@@ -42,6 +44,8 @@ pub enum RpcError {
     AuthError(AuthStat),
     /// Failed to register with portmap
     Portmap,
+    /// Call was handled but no reply should be sent (e.g. one-way calls)
+    DoNotReply,
     /// (De-)serialiation error on RPC channel
     Io(Error),
 }
@@ -58,18 +62,41 @@ pub(crate) trait RpcService {
         Ok(())
     }
 
+    /// Record size statistics, if collected
+    fn stats(&self) -> Option<&MessageStats> {
+        None
+    }
+
+    /// Largest record accepted from a stream. A client sending a larger record is disconnected.
+    fn max_record_size(&self) -> usize {
+        1024 * 1024
+    }
+
     async fn serve_tcp_stream(self: Arc<Self>, mut stream: TcpStream) -> io::Result<()>
     where
         Self: Sync,
     {
         loop {
             // Read message
-            let fragment = read_record(&mut stream, 1024 * 1024).await?;
+            let fragment = match read_next_record(&mut stream, self.max_record_size()).await? {
+                Some(fragment) => fragment,
+                None => {
+                    log::info!("Client disconnected");
+                    return Ok(());
+                }
+            };
+            if let Some(stats) = self.stats() {
+                stats.inbound.record(fragment.len());
+            }
 
             let reply = self.clone().handle_message(fragment).await?;
 
-            write_record(&mut stream, reply).await?;
-            //stream.write_all(&reply).await?;
+            if let Some(reply) = reply {
+                if let Some(stats) = self.stats() {
+                    stats.outbound.record(reply.len());
+                }
+                write_record(&mut stream, reply).await?;
+            }
         }
     }
 
@@ -79,7 +106,16 @@ pub(crate) trait RpcService {
     {
         loop {
             // Read message
-            let fragment = read_record(&mut stream, 1024 * 1024).await?;
+            let fragment = match read_next_record(&mut stream, self.max_record_size()).await? {
+                Some(fragment) => fragment,
+                None => {
+                    log::info!("Client disconnected");
+                    return Ok(());
+                }
+            };
+            if let Some(stats) = self.stats() {
+                stats.inbound.record(fragment.len());
+            }
 
             let _reply = self.clone().handle_message(fragment).await?;
         }
@@ -93,14 +129,32 @@ pub(crate) trait RpcService {
             // Read message
             let mut buf = vec![0; 1500];
             let (n, peer) = socket.recv_from(&mut buf).await?;
+            if let Some(stats) = self.stats() {
+                stats.inbound.record(n);
+            }
 
-            let reply = self.clone().handle_message(buf[..n].to_vec()).await?;
+            // A bad datagram (e.g. from a broadcast) should not stop the service
+            let reply = match self.clone().handle_message(buf[..n].to_vec()).await {
+                Ok(reply) => reply,
+                Err(err) => {
+                    log::debug!("Invalid message from {}: {}", peer, err);
+                    continue;
+                }
+            };
 
-            socket.send_to(&reply, peer).await?;
+            if let Some(reply) = reply {
+                if let Some(stats) = self.stats() {
+                    stats.outbound.record(reply.len());
+                }
+                if let Err(err) = socket.send_to(&reply, peer).await {
+                    log::debug!("Failed to reply to {}: {}", peer, err);
+                }
+            }
         }
     }
 
-    async fn handle_message(self: Arc<Self>, data_in: Vec<u8>) -> Result<Vec<u8>, Error>
+    /// Handle a single call message. Returns the serialized reply or `None` if no reply should be sent.
+    async fn handle_message(self: Arc<Self>, data_in: Vec<u8>) -> Result<Option<Vec<u8>>, Error>
     where
         Self: Sync,
     {
@@ -135,6 +189,10 @@ pub(crate) trait RpcService {
                         RpcError::GarbageArgs => xdr::AcceptStat::GarbageArgs,
                         RpcError::SystemErr => xdr::AcceptStat::SystemErr,
                         RpcError::Io(err) => return Err(err),
+                        RpcError::DoNotReply => {
+                            log::trace!("<- (no reply)");
+                            return Ok(None);
+                        }
                         RpcError::RpcMissmatch(_) => unreachable!(),
                         RpcError::AuthError(_) => unreachable!(),
                         RpcError::Portmap => unreachable!(),
@@ -161,7 +219,7 @@ pub(crate) trait RpcService {
         reply.write_xdr(&mut data_out)?;
         data_out.write_all(&ret.into_inner()[..])?;
 
-        Ok(data_out.into_inner())
+        Ok(Some(data_out.into_inner()))
     }
 
     async fn call(
@@ -263,7 +321,8 @@ impl UdpRpcClient {
                 mtype: xdr::MsgType::Call(..),
                 ..
             } => {
-                todo!()
+                log::debug!("Received call in place of reply");
+                Err(Error::from(ErrorKind::InvalidData).into())
             }
         }
     }
@@ -379,7 +438,8 @@ where
                 mtype: xdr::MsgType::Call(..),
                 ..
             } => {
-                todo!()
+                log::debug!("Received call in place of reply");
+                Err(Error::from(ErrorKind::InvalidData).into())
             }
         }
     }
@@ -449,4 +509,180 @@ impl RpcClient {
             RpcClient::Tcp(client) => client.call_no_reply(proc, args).await,
         }
     }
+
+    /// Local address of underlying socket
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            RpcClient::Udp(client) => client.socket.local_addr(),
+            RpcClient::Tcp(client) => client.io.local_addr(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc};
+
+    use async_std::{
+        net::{TcpListener, TcpStream},
+        os::unix::net::UnixStream,
+    };
+    use futures::{join, AsyncWriteExt};
+
+    use super::record::{read_record, write_record};
+    use super::{xdr, RpcError, RpcService, StreamRpcClient};
+    use crate::common::xdr::prelude::*;
+
+    /// Reply to a single call with `stat`
+    async fn reply_with(mut stream: UnixStream, stat: xdr::ReplyStat) {
+        let call = read_record(&mut stream, 1024).await.unwrap();
+        let mut msg = xdr::RpcMessage::default();
+        msg.read_xdr(&mut Cursor::new(call)).unwrap();
+
+        let reply = xdr::RpcMessage {
+            xid: msg.xid,
+            mtype: xdr::MsgType::Reply(xdr::Replybody { stat }),
+        };
+        let mut data = Cursor::new(Vec::new());
+        reply.write_xdr(&mut data).unwrap();
+        write_record(&mut stream, data.into_inner()).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn denied_rpc_missmatch() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let mut client = StreamRpcClient::new(client, 1, 1);
+
+        let (res, _) = join!(
+            client.null(),
+            reply_with(server, xdr::ReplyStat::rpc_vers_missmatch(2, 2))
+        );
+        assert!(matches!(
+            res,
+            Err(RpcError::RpcMissmatch(xdr::MissmatchInfo {
+                low: 2,
+                high: 2
+            }))
+        ));
+    }
+
+    #[async_std::test]
+    async fn denied_auth_error() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let mut client = StreamRpcClient::new(client, 1, 1);
+
+        let (res, _) = join!(
+            client.null(),
+            reply_with(
+                server,
+                xdr::ReplyStat::auth_error(xdr::AuthStat::RejectedCred)
+            )
+        );
+        assert!(matches!(
+            res,
+            Err(RpcError::AuthError(xdr::AuthStat::RejectedCred))
+        ));
+    }
+
+    struct OneWayService;
+
+    #[async_trait::async_trait]
+    impl RpcService for OneWayService {
+        async fn call(
+            self: Arc<Self>,
+            _prog: u32,
+            _vers: u32,
+            proc: u32,
+            _args: &mut Cursor<Vec<u8>>,
+            _ret: &mut Cursor<Vec<u8>>,
+        ) -> Result<(), RpcError> {
+            match proc {
+                0 => Ok(()),
+                _ => Err(RpcError::DoNotReply),
+            }
+        }
+    }
+
+    fn call_message(proc: u32) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        xdr::RpcMessage::call(1, 1, 1, proc)
+            .write_xdr(&mut data)
+            .unwrap();
+        data.into_inner()
+    }
+
+    #[async_std::test]
+    async fn do_not_reply() {
+        let service = Arc::new(OneWayService);
+
+        let reply = service
+            .clone()
+            .handle_message(call_message(0))
+            .await
+            .unwrap();
+        assert!(reply.is_some());
+
+        let reply = service.handle_message(call_message(1)).await.unwrap();
+        assert!(reply.is_none());
+    }
+
+    struct SmallService;
+
+    impl RpcService for SmallService {
+        fn max_record_size(&self) -> usize {
+            16
+        }
+    }
+
+    #[async_std::test]
+    async fn record_too_large() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        // Header claiming a 2GB record
+        client.write_all(b"\xff\xff\xff\xff").await.unwrap();
+
+        let err = Arc::new(SmallService)
+            .serve_tcp_stream(server)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::OutOfMemory);
+    }
+
+    #[async_std::test]
+    async fn client_disconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        drop(client);
+
+        Arc::new(SmallService)
+            .serve_tcp_stream(server)
+            .await
+            .unwrap();
+    }
+    #[async_std::test]
+    async fn client_disconnect_in_record() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        // Truncated record
+        client.write_all(b"\x80\x00\x00\x08\x01\x02").await.unwrap();
+        drop(client);
+
+        let err = Arc::new(SmallService)
+            .serve_tcp_stream(server)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
 }
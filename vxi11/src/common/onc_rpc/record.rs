@@ -4,30 +4,47 @@ use byteorder::{ByteOrder, NetworkEndian};
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub(crate) async fn read_record<RD>(reader: &mut RD, maxlen: usize) -> Result<Vec<u8>>
+where
+    RD: AsyncRead + Unpin,
+{
+    read_next_record(reader, maxlen)
+        .await?
+        .ok_or_else(|| ErrorKind::UnexpectedEof.into())
+}
+
+/// Same as [read_record] but returns `None` if the stream ends before the next record starts.
+pub(crate) async fn read_next_record<RD>(reader: &mut RD, maxlen: usize) -> Result<Option<Vec<u8>>>
 where
     RD: AsyncRead + Unpin,
 {
     let mut buf = Vec::new();
+    let mut first = true;
 
     loop {
         // Read record header
         let mut fragment_header = [0u8; 4];
-        reader.read_exact(&mut fragment_header).await?;
+        let n = reader.read(&mut fragment_header).await?;
+        if n == 0 && first {
+            return Ok(None);
+        }
+        reader.read_exact(&mut fragment_header[n..]).await?;
         let fragment_len = NetworkEndian::read_u32(&fragment_header[..]);
+        first = false;
 
         // Assemble record
         let len = (fragment_len & 0x7FFFFFFF) as usize;
         if buf.len() + len > maxlen || buf.try_reserve(len).is_err() {
             return Err(ErrorKind::OutOfMemory.into());
         }
-        reader
-            .take((fragment_len & 0x7FFFFFFF) as u64)
-            .read_to_end(&mut buf)
-            .await?;
+        let n = reader.take(len as u64).read_to_end(&mut buf).await?;
+        if n != len {
+            // Stream ended in the middle of a fragment
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
 
         // Check if last fragment
         if fragment_len & 0x80000000 != 0 {
-            break Ok(buf);
+            break Ok(Some(buf));
         }
     }
 }
@@ -66,4 +83,44 @@ mod tests {
 
         assert_eq!(rec[..], [1, 2, 3, 4])
     }
+
+    #[async_std::test]
+    async fn reassemble_consecutive_records() {
+        let mut cursor = Cursor::new(
+            b"\x00\x00\x00\x01\x01\x80\x00\x00\x01\x02\x00\x00\x00\x00\x80\x00\x00\x01\x03",
+        );
+        let rec = super::read_record(&mut cursor, 10).await.unwrap();
+        assert_eq!(rec[..], [1, 2]);
+
+        let rec = super::read_record(&mut cursor, 10).await.unwrap();
+        assert_eq!(rec[..], [3])
+    }
+
+    #[async_std::test]
+    async fn truncated_fragment() {
+        let mut cursor = Cursor::new(b"\x00\x00\x00\x02\x01\x02\x80\x00\x00\x04\x03\x04");
+        let err = super::read_record(&mut cursor, 10).await.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof)
+    }
+
+    #[async_std::test]
+    async fn end_of_stream() {
+        // Ended between records
+        let mut cursor = Cursor::new(b"\x80\x00\x00\x01\x01");
+        let rec = super::read_next_record(&mut cursor, 10).await.unwrap();
+        assert_eq!(rec.as_deref(), Some(&[1][..]));
+        let rec = super::read_next_record(&mut cursor, 10).await.unwrap();
+        assert!(rec.is_none());
+
+        // Ended in a record header
+        let mut cursor = Cursor::new(b"\x80\x00");
+        let err = super::read_next_record(&mut cursor, 10).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // Ended in a following fragment header
+        let mut cursor = Cursor::new(b"\x00\x00\x00\x01\x01");
+        let err = super::read_next_record(&mut cursor, 10).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
 }
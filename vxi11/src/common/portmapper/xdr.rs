@@ -48,6 +48,42 @@ impl XdrDecode for Mapping {
     }
 }
 
+/// List of mappings as returned by the DUMP procedure
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MappingList(pub(crate) Vec<Mapping>);
+
+impl XdrEncode for MappingList {
+    fn write_xdr<WR>(&self, writer: &mut WR) -> Result<()>
+    where
+        WR: Write,
+    {
+        for mapping in self.0.iter() {
+            true.write_xdr(writer)?;
+            mapping.write_xdr(writer)?;
+        }
+        false.write_xdr(writer)
+    }
+}
+
+impl XdrDecode for MappingList {
+    fn read_xdr<RD>(&mut self, reader: &mut RD) -> Result<()>
+    where
+        RD: Read,
+    {
+        self.0.clear();
+        loop {
+            let mut more = false;
+            more.read_xdr(reader)?;
+            if !more {
+                break Ok(());
+            }
+            let mut mapping = Mapping::default();
+            mapping.read_xdr(reader)?;
+            self.0.push(mapping);
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub(crate) struct Callit {
     pub(crate) prog: u32,
@@ -49,6 +49,8 @@ impl<const N: usize> StaticPortMap<N> {
     }
 
     /// Serve UDP calls
+    ///
+    /// Bind `socket` to an unspecified address (e.g. `0.0.0.0:111`) to also answer broadcast discovery queries.
     pub async fn serve_udp(self: Arc<Self>, socket: UdpSocket) -> io::Result<()> {
         log::info!("Listening on UDP {:?}", socket.local_addr()?);
         self.serve_udp_socket(socket).await
@@ -64,7 +66,14 @@ impl<const N: usize> StaticPortMap<N> {
             .backpressure(10);
 
         while let Some((token, stream)) = incoming.next().await {
-            let peer = stream.peer_addr()?;
+            // Client may already have disconnected, skip it and keep accepting
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(err) => {
+                    log::warn!("Failed to get peer address: {}", err);
+                    continue;
+                }
+            };
             log::debug!("Accepted from: {}", peer);
 
             let s = self.clone();
@@ -115,11 +124,7 @@ impl<const N: usize> RpcService for StaticPortMap<N> {
                 Ok(())
             }
             PMAPPROC_DUMP => {
-                for mapping in self.mappings.iter() {
-                    true.write_xdr(ret)?;
-                    mapping.write_xdr(ret)?;
-                }
-                false.write_xdr(ret)?;
+                xdr::MappingList(self.mappings.to_vec()).write_xdr(ret)?;
                 Ok(())
             }
             _ => Err(RpcError::ProcUnavail),
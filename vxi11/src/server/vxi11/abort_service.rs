@@ -22,6 +22,7 @@ use super::{prelude::*, VxiInner};
 pub struct VxiAsyncServer<DEV> {
     pub(super) inner: Arc<Mutex<VxiInner<DEV>>>,
     pub(super) async_port: u16,
+    pub(super) max_connections: usize,
 }
 
 impl<DEV> VxiAsyncServer<DEV>
@@ -39,10 +40,17 @@ where
             .incoming()
             .log_warnings(|warn| log::warn!("Listening error: {}", warn))
             .handle_errors(Duration::from_millis(100))
-            .backpressure(10);
+            .backpressure(self.max_connections);
 
         while let Some((token, stream)) = incoming.next().await {
-            let peer = stream.peer_addr()?;
+            // Client may already have disconnected, skip it and keep accepting
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(err) => {
+                    log::warn!("Failed to get peer address: {}", err);
+                    continue;
+                }
+            };
             log::debug!("Accepted from: {}", peer);
 
             let s = self.clone();
@@ -94,7 +102,7 @@ where
 
                 let mut resp = xdr::DeviceError::default();
 
-                // TODO
+                // Abort any operation waiting on the link
                 let sender = {
                     let inner = self.inner.lock().await;
                     inner.links.get(&parms.0).cloned()
@@ -1,7 +1,7 @@
 use std::{
-    cmp::min,
     collections::HashMap,
     io::{self, Cursor},
+    mem,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::Arc,
     time::Duration,
@@ -13,7 +13,7 @@ use async_std::{
     net::TcpListener,
     task::{self, JoinHandle},
 };
-use lxi_device::{lock::SharedLockError, trigger::Source, Device};
+use lxi_device::{lock::SharedLockError, stats::MessageStats, trigger::Source, AsyncDevice};
 
 use crate::common::{
     onc_rpc::prelude::*,
@@ -34,6 +34,8 @@ macro_rules! get_link {
 macro_rules! lock_device {
     ($handle:expr, $flags:expr, $timeout:expr, $abort:expr) => {
         if $flags.is_waitlock() {
+            // Discard aborts received while no operation was in progress
+            while let Some(Some(())) = $abort.next().now_or_never() {}
             select! {
                 d = timeout(
                     Duration::from_millis($timeout as u64),
@@ -52,11 +54,14 @@ pub struct VxiCoreServer<DEV> {
     pub(super) inner: Arc<Mutex<VxiInner<DEV>>>,
     pub(super) max_recv_size: u32,
     pub(super) async_port: u16,
+    pub(super) stats: Option<Arc<MessageStats>>,
+    pub(super) max_record_size: usize,
+    pub(super) max_connections: usize,
 }
 
 impl<DEV> VxiCoreServer<DEV>
 where
-    DEV: Device + Send + 'static,
+    DEV: AsyncDevice,
 {
     pub async fn bind(self: Arc<Self>, addrs: IpAddr) -> io::Result<()> {
         let listener = TcpListener::bind((addrs, self.async_port)).await?;
@@ -69,9 +74,16 @@ where
             .incoming()
             .log_warnings(|warn| log::warn!("Listening error: {}", warn))
             .handle_errors(Duration::from_millis(100))
-            .backpressure(10);
+            .backpressure(self.max_connections);
         while let Some((token, stream)) = incoming.next().await {
-            let peer = stream.peer_addr()?;
+            // Client may already have disconnected, skip it and keep accepting
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(err) => {
+                    log::warn!("Failed to get peer address: {}", err);
+                    continue;
+                }
+            };
             log::debug!("Accepted from: {}", peer);
             let s = Arc::new(VxiCoreSession {
                 peer,
@@ -80,6 +92,8 @@ where
                 async_port: self.async_port,
                 links: Mutex::new(HashMap::new()),
                 srq: Arc::new(Mutex::new(None)),
+                stats: self.stats.clone(),
+                max_record_size: self.max_record_size,
             });
 
             task::spawn(async move {
@@ -105,13 +119,24 @@ pub struct VxiCoreSession<DEV> {
     links: Mutex<HashMap<u32, Link<DEV>>>,
 
     srq: Arc<Mutex<Option<VxiSrqClient>>>,
+
+    stats: Option<Arc<MessageStats>>,
+    max_record_size: usize,
 }
 
 #[async_trait::async_trait]
 impl<DEV> RpcService for VxiCoreSession<DEV>
 where
-    DEV: Device + Send + 'static,
+    DEV: AsyncDevice,
 {
+    fn stats(&self) -> Option<&MessageStats> {
+        self.stats.as_deref()
+    }
+
+    fn max_record_size(&self) -> usize {
+        self.max_record_size
+    }
+
     async fn call(
         self: Arc<Self>,
         prog: u32,
@@ -147,29 +172,35 @@ where
                     max_recv_size: self.max_recv_size,
                 };
 
-                let mut inner = self.inner.lock().await;
-                resp.error = match inner.new_link(&parms.device) {
+                let res = self.inner.lock().await.new_link(&parms.device);
+                resp.error = match res {
                     Ok((lid, mut link)) => {
-                        resp.lid = lid.into();
-
                         // Try to lock
-                        if parms.lock_device {
-                            let res = timeout(
+                        let res = if parms.lock_device {
+                            timeout(
                                 Duration::from_millis(parms.lock_timeout as u64),
                                 link.handle.async_acquire_exclusive(),
                             )
                             .await
-                            .map_or(Err(SharedLockError::Timeout), |f| f);
-                            match res {
-                                Ok(()) => {
-                                    log::debug!(peer=format!("{}", self.peer), link=lid; "Exclusive lock acquired")
-                                }
-                                Err(err) => resp.error = err.into(),
+                            .map_or(Err(SharedLockError::Timeout), |f| f)
+                        } else {
+                            Ok(())
+                        };
+
+                        match res {
+                            Ok(()) => {
+                                log::debug!(peer=format!("{}", self.peer), link=lid; "New link: {}, client_id={}, locked={}", parms.device, parms.client_id, parms.lock_device);
+                                resp.lid = lid.into();
+                                self.links.lock().await.insert(lid, link);
+                                xdr::DeviceErrorCode::NoError
+                            }
+                            Err(err) => {
+                                // Link is dropped and never handed to the client
+                                log::debug!(peer=format!("{}", self.peer), link=lid; "Failed to lock new link, {:?}: {}", err, parms.device);
+                                self.inner.lock().await.remove_link(lid);
+                                err.into()
                             }
                         }
-                        log::debug!(peer=format!("{}", self.peer), link=lid; "New link: {}, client_id={}", parms.device, parms.client_id);
-                        self.links.lock().await.insert(lid, link);
-                        xdr::DeviceErrorCode::NoError
                     }
                     Err(err) => {
                         log::debug!(peer=format!("{}", self.peer); "Failed to create new link, {:?}: {}", err, parms.device);
@@ -201,7 +232,7 @@ where
 
                         // Execute if END is set
                         match dev {
-                            Ok(mut dev) => {
+                            Ok(dev) => {
                                 link.in_buf
                                     .try_reserve(parms.data.len())
                                     .map_err(|_| RpcError::SystemErr)?;
@@ -209,11 +240,11 @@ where
                                 resp.size = parms.data.0.len() as u32;
 
                                 if parms.flags.is_end() {
-                                    if let Some(v) = dev.execute(&link.in_buf) {
+                                    let cmd = mem::take(&mut link.in_buf);
+                                    if let Some(v) = AsyncDevice::execute_async(dev, cmd).await {
                                         //log::debug!(link=parms.lid.0; "Execute {:?} -> {:?}", link.in_buf, v);
-                                        link.out_buf.extend(&v);
+                                        v.extend_into(&mut link.out_buf).await;
                                     }
-                                    link.in_buf.clear();
                                 }
                                 xdr::DeviceErrorCode::NoError
                             }
@@ -251,27 +282,25 @@ where
                     // Execute if END is set
                     resp.error = match dev {
                         Ok(_) => {
-                            let to_take = if parms.flags.is_termcharset() {
-                                let pos = link.out_buf.iter().position(|c| c.eq(&parms.term_char));
-
-                                // Take whatever is first terminator, or end
-                                let to_take = pos.map_or(parms.request_size as usize, |x| {
-                                    min(link.out_buf.len(), x + 1)
-                                });
-                                // Returning because of term_char
-                                if matches!(pos, Some(c) if c == to_take) {
-                                    resp.reason |= 0x2;
-                                }
-                                to_take
+                            let request_size = parms.request_size as usize;
+                            let term = if parms.flags.is_termcharset() {
+                                link.out_buf.iter().position(|c| c.eq(&parms.term_char))
                             } else {
-                                link.out_buf.len()
-                            }
-                            .min(parms.request_size as usize);
+                                None
+                            };
+
+                            // Take up to and including first terminator, or everything
+                            let to_take =
+                                term.map_or(link.out_buf.len(), |x| x + 1).min(request_size);
 
                             // Returning because of request_size
-                            if to_take == parms.request_size as usize {
+                            if to_take == request_size {
                                 resp.reason |= 0x1;
                             }
+                            // Returning because of term_char
+                            if matches!(term, Some(x) if x + 1 == to_take) {
+                                resp.reason |= 0x2;
+                            }
                             // Returning because of end
                             if to_take == link.out_buf.len() {
                                 resp.reason |= 0x4;
@@ -318,7 +347,7 @@ where
                                     // Replace MAV bit
                                     resp.stb &= 0xef;
                                     if !link.out_buf.is_empty() {
-                                        resp.stb &= 0x10;
+                                        resp.stb |= 0x10;
                                     }
                                     xdr::DeviceErrorCode::NoError
                                 }
@@ -382,8 +411,6 @@ where
                 let resp = xdr::DeviceError {
                     error: match get_link!(self.links, &parms.lid.0) {
                         Some(link) => {
-                            link.clear();
-
                             let dev = lock_device!(
                                 link.handle,
                                 parms.flags,
@@ -391,8 +418,13 @@ where
                                 link.abort
                             );
 
+                            // Buffers are only cleared if the link may access the device
                             match dev {
-                                Ok(mut d) => d.clear().into(),
+                                Ok(mut d) => {
+                                    link.in_buf.clear();
+                                    link.out_buf.clear();
+                                    d.clear().into()
+                                }
                                 Err(err) => err.into(),
                             }
                         }
@@ -450,14 +482,18 @@ where
 
                 let resp = xdr::DeviceError {
                     error: match get_link!(self.links, &parms.lid.0) {
-                        Some(link) if parms.flags.is_waitlock() => select! {
-                            d = timeout(
-                                Duration::from_millis(parms.lock_timeout as u64),
-                                link.handle.async_acquire_exclusive(),
-                            ).fuse() => d.map_or(Err(SharedLockError::Timeout), |f| f),
-                            _ = link.abort.next() => Err(SharedLockError::Aborted)
+                        Some(link) if parms.flags.is_waitlock() => {
+                            // Discard aborts received while no operation was in progress
+                            while let Some(Some(())) = link.abort.next().now_or_never() {}
+                            select! {
+                                d = timeout(
+                                    Duration::from_millis(parms.lock_timeout as u64),
+                                    link.handle.async_acquire_exclusive(),
+                                ).fuse() => d.map_or(Err(SharedLockError::Timeout), |f| f),
+                                _ = link.abort.next() => Err(SharedLockError::Aborted)
+                            }
+                            .into()
                         }
-                        .into(),
                         Some(link) => link.handle.try_acquire_exclusive().into(),
                         None => xdr::DeviceErrorCode::InvalidLinkIdentifier,
                     },
@@ -507,7 +543,7 @@ where
                             let old = if parms.enable {
                                 let client = self.srq.clone();
                                 let mut inner = self.inner.lock().await;
-                                let mut reader = inner.status.get_new_receiver();
+                                let mut reader = inner.status.get_new_receiver_with_latest();
 
                                 // Spawn a new tasks which monitors srq events
                                 let fut: JoinHandle<Result<(), RpcError>> = task::spawn(
@@ -565,7 +601,24 @@ where
 
                 log::debug!(peer=format!("{}", self.peer), link=parms.lid.0; "Docmd {}, data={:?}", parms.cmd, parms.data_in);
 
-                resp.error = xdr::DeviceErrorCode::OperationNotSupported;
+                resp.error = match get_link!(self.links, &parms.lid.0) {
+                    Some(link) => {
+                        let dev =
+                            lock_device!(link.handle, parms.flags, parms.lock_timeout, link.abort);
+
+                        match dev {
+                            Ok(mut d) => match d.docmd(parms.cmd, &parms.data_in.0) {
+                                Ok(data) => {
+                                    resp.data_out = Opaque(data);
+                                    xdr::DeviceErrorCode::NoError
+                                }
+                                Err(err) => err.into(),
+                            },
+                            Err(err) => err.into(),
+                        }
+                    }
+                    None => xdr::DeviceErrorCode::InvalidLinkIdentifier,
+                };
 
                 // Write response
                 resp.write_xdr(ret)?;
@@ -607,10 +660,23 @@ where
 
                 let mut resp = xdr::DeviceError::default();
 
+                // Only allow the client to direct interrupts at itself
+                let host = Ipv4Addr::from(parms.host_addr);
+                let is_peer = match self.peer.ip() {
+                    IpAddr::V4(peer) => peer == host,
+                    IpAddr::V6(peer) => peer.to_ipv4_mapped() == Some(host),
+                };
+
                 let mut srq = self.srq.lock().await;
 
                 if srq.is_some() {
                     resp.error = xdr::DeviceErrorCode::ChannelAlreadyEstablished
+                } else if parms.prog_family == xdr::DeviceAddrFamily::_Invalid {
+                    log::warn!(peer=format!("{}", self.peer); "Unsupported interrupt channel protocol");
+                    resp.error = xdr::DeviceErrorCode::ParameterError
+                } else if !is_peer {
+                    log::warn!(peer=format!("{}", self.peer); "Refused interrupt channel to other host {}", host);
+                    resp.error = xdr::DeviceErrorCode::ChannelNotEstablished
                 } else if let Ok(client) = VxiSrqClient::new(
                     parms.host_addr,
                     parms.host_port,
@@ -652,3 +718,679 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Cursor,
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+
+    use async_std::{
+        net::{TcpListener, TcpStream},
+        task,
+    };
+    use futures::{lock::Mutex, AsyncReadExt, FutureExt};
+    use lxi_device::{
+        lock::SharedLock, status::Sender as StatusSender, trigger::Source, util::EchoDevice,
+        AsyncDevice, Device, DeviceError, ExecuteOutput,
+    };
+
+    use super::VxiCoreSession;
+    use crate::common::{
+        onc_rpc::prelude::*,
+        vxi11::{
+            self, xdr, DEVICE_ASYNC, DEVICE_ASYNC_VERSION, DEVICE_CORE, DEVICE_CORE_VERSION,
+            DEVICE_INTR, DEVICE_INTR_SRQ, DEVICE_INTR_VERSION,
+        },
+        xdr::prelude::*,
+    };
+    use crate::server::vxi11::{VxiAsyncServer, VxiInner, VxiServerBuilder};
+
+    fn session<DEV>(inner: Arc<Mutex<VxiInner<DEV>>>) -> Arc<VxiCoreSession<DEV>> {
+        Arc::new(VxiCoreSession {
+            peer: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+            inner,
+            max_recv_size: 1024,
+            async_port: 0,
+            links: Mutex::new(HashMap::new()),
+            srq: Arc::new(Mutex::new(None)),
+            stats: None,
+            max_record_size: 1024 * 1024,
+        })
+    }
+
+    async fn create_link<DEV>(
+        session: &Arc<VxiCoreSession<DEV>>,
+        lock_device: bool,
+    ) -> xdr::CreateLinkResp
+    where
+        DEV: AsyncDevice,
+    {
+        let parms = xdr::CreateLinkParms {
+            client_id: 0,
+            lock_device,
+            lock_timeout: 0,
+            device: "inst0".to_string(),
+        };
+        let mut args = Cursor::new(Vec::new());
+        parms.write_xdr(&mut args).unwrap();
+        args.set_position(0);
+
+        let mut ret = Cursor::new(Vec::new());
+        session
+            .clone()
+            .call(
+                DEVICE_CORE,
+                DEVICE_CORE_VERSION,
+                vxi11::CREATE_LINK,
+                &mut args,
+                &mut ret,
+            )
+            .await
+            .unwrap();
+        ret.set_position(0);
+
+        let mut resp = xdr::CreateLinkResp::default();
+        resp.read_xdr(&mut ret).unwrap();
+        resp
+    }
+
+    #[async_std::test]
+    async fn test_create_link_locked() {
+        let mut devices = HashMap::new();
+        devices.insert(
+            "inst0".to_string(),
+            (EchoDevice::new_arc(), SharedLock::new()),
+        );
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let session1 = session(inner.clone());
+        let session2 = session(inner.clone());
+
+        // First link holds the lock
+        let resp = create_link(&session1, true).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+
+        // Second link cannot lock and is not created
+        let resp = create_link(&session2, true).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::DeviceLockedByAnotherLink);
+        assert!(session2.links.lock().await.is_empty());
+        assert_eq!(inner.lock().await.links.len(), 1);
+
+        // Linking without a lock is still fine
+        let resp = create_link(&session2, false).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+    }
+
+    async fn create_intr_chan(
+        session: &Arc<VxiCoreSession<EchoDevice>>,
+        host: Ipv4Addr,
+        family: u32,
+    ) -> xdr::DeviceErrorCode {
+        let mut args = Cursor::new(Vec::new());
+        u32::from(host).write_xdr(&mut args).unwrap();
+        0u16.write_xdr(&mut args).unwrap();
+        DEVICE_INTR.write_xdr(&mut args).unwrap();
+        DEVICE_INTR_VERSION.write_xdr(&mut args).unwrap();
+        family.write_xdr(&mut args).unwrap();
+        args.set_position(0);
+
+        let mut ret = Cursor::new(Vec::new());
+        session
+            .clone()
+            .call(
+                DEVICE_CORE,
+                DEVICE_CORE_VERSION,
+                vxi11::CREATE_INTR_CHAN,
+                &mut args,
+                &mut ret,
+            )
+            .await
+            .unwrap();
+        ret.set_position(0);
+
+        let mut resp = xdr::DeviceError::default();
+        resp.read_xdr(&mut ret).unwrap();
+        resp.error
+    }
+
+    #[async_std::test]
+    async fn test_create_intr_chan_refused() {
+        let inner = VxiInner::new(HashMap::new(), StatusSender::new());
+        let session = session(inner);
+
+        // Interrupt channel to another host than client
+        let error = create_intr_chan(&session, Ipv4Addr::new(10, 1, 2, 3), 0).await;
+        assert_eq!(error, xdr::DeviceErrorCode::ChannelNotEstablished);
+
+        // Unknown protocol
+        let error = create_intr_chan(&session, Ipv4Addr::LOCALHOST, 2).await;
+        assert_eq!(error, xdr::DeviceErrorCode::ParameterError);
+
+        assert!(session.srq.lock().await.is_none());
+    }
+
+    async fn call<DEV, A, R>(session: &Arc<VxiCoreSession<DEV>>, procedure: u32, parms: A) -> R
+    where
+        DEV: AsyncDevice,
+        A: XdrEncode,
+        R: XdrDecode + Default,
+    {
+        let mut args = Cursor::new(Vec::new());
+        parms.write_xdr(&mut args).unwrap();
+        args.set_position(0);
+
+        let mut ret = Cursor::new(Vec::new());
+        session
+            .clone()
+            .call(
+                DEVICE_CORE,
+                DEVICE_CORE_VERSION,
+                procedure,
+                &mut args,
+                &mut ret,
+            )
+            .await
+            .unwrap();
+        ret.set_position(0);
+
+        let mut resp = R::default();
+        resp.read_xdr(&mut ret).unwrap();
+        resp
+    }
+
+    #[async_std::test]
+    async fn test_write_read() {
+        let mut devices = HashMap::new();
+        devices.insert(
+            "inst0".to_string(),
+            (EchoDevice::new_arc(), SharedLock::new()),
+        );
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let session = session(inner);
+        let lid = create_link(&session, false).await.lid;
+
+        let write = |data: &[u8], flags: u32| xdr::DeviceWriteParms {
+            lid,
+            io_timeout: 0,
+            lock_timeout: 0,
+            flags: xdr::DeviceFlags(flags),
+            data: Opaque(data.to_vec()),
+        };
+        let read = |request_size: u32, flags: u32, term_char: u8| xdr::DeviceReadParms {
+            lid,
+            request_size,
+            io_timeout: 0,
+            lock_timeout: 0,
+            flags: xdr::DeviceFlags(flags),
+            term_char,
+        };
+
+        // Command is executed when END is set
+        let resp: xdr::DeviceWriteResp =
+            call(&session, vxi11::DEVICE_WRITE, write(b"*IDN", 0)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        assert_eq!(resp.size, 4);
+        let resp: xdr::DeviceWriteResp =
+            call(&session, vxi11::DEVICE_WRITE, write(b"?\n", 0x08)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        assert_eq!(resp.size, 2);
+
+        // Whole response, END
+        let resp: xdr::DeviceReadResp = call(&session, vxi11::DEVICE_READ, read(1024, 0, 0)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        assert_eq!(resp.data.0, b"*IDN?\n");
+        assert_eq!(resp.reason, 0x4);
+
+        // Limited by request size, REQCNT
+        let _: xdr::DeviceWriteResp =
+            call(&session, vxi11::DEVICE_WRITE, write(b"AB\nCD", 0x08)).await;
+        let resp: xdr::DeviceReadResp = call(&session, vxi11::DEVICE_READ, read(1, 0, 0)).await;
+        assert_eq!(resp.data.0, b"A");
+        assert_eq!(resp.reason, 0x1);
+
+        // Stops at term char, CHR
+        let resp: xdr::DeviceReadResp =
+            call(&session, vxi11::DEVICE_READ, read(1024, 0x80, b'\n')).await;
+        assert_eq!(resp.data.0, b"B\n");
+        assert_eq!(resp.reason, 0x2);
+
+        // Term char not found, END
+        let resp: xdr::DeviceReadResp =
+            call(&session, vxi11::DEVICE_READ, read(1024, 0x80, b'\n')).await;
+        assert_eq!(resp.data.0, b"CD");
+        assert_eq!(resp.reason, 0x4);
+
+        // Unknown link
+        let resp: xdr::DeviceReadResp = call(
+            &session,
+            vxi11::DEVICE_READ,
+            xdr::DeviceReadParms {
+                lid: xdr::DeviceLink(lid.0 + 1),
+                ..read(1024, 0, 0)
+            },
+        )
+        .await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::InvalidLinkIdentifier);
+    }
+
+    #[async_std::test]
+    async fn test_abort() {
+        let mut devices = HashMap::new();
+        devices.insert(
+            "inst0".to_string(),
+            (EchoDevice::new_arc(), SharedLock::new()),
+        );
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let abort = Arc::new(VxiAsyncServer {
+            inner: inner.clone(),
+            async_port: 0,
+            max_connections: 1,
+        });
+        let abort_link = |lid: xdr::DeviceLink| {
+            let abort = abort.clone();
+            async move {
+                let mut args = Cursor::new(Vec::new());
+                lid.write_xdr(&mut args).unwrap();
+                args.set_position(0);
+                let mut ret = Cursor::new(Vec::new());
+                abort
+                    .call(
+                        DEVICE_ASYNC,
+                        DEVICE_ASYNC_VERSION,
+                        vxi11::DEVICE_ABORT,
+                        &mut args,
+                        &mut ret,
+                    )
+                    .await
+                    .unwrap();
+                ret.set_position(0);
+                let mut resp = xdr::DeviceError::default();
+                resp.read_xdr(&mut ret).unwrap();
+                resp.error
+            }
+        };
+
+        // Other link holds the lock
+        let locked = session(inner.clone());
+        assert_eq!(
+            create_link(&locked, true).await.error,
+            xdr::DeviceErrorCode::NoError
+        );
+        let session = session(inner);
+        let lid = create_link(&session, false).await.lid;
+
+        // Stale abort does not affect next operation
+        assert_eq!(abort_link(lid).await, xdr::DeviceErrorCode::NoError);
+
+        // Read waits for lock until aborted
+        let read = xdr::DeviceReadParms {
+            lid,
+            request_size: 1024,
+            io_timeout: 10000,
+            lock_timeout: 10000,
+            flags: xdr::DeviceFlags(0x01),
+            term_char: 0,
+        };
+        let start = std::time::Instant::now();
+        let read = call::<_, _, xdr::DeviceReadResp>(&session, vxi11::DEVICE_READ, read).fuse();
+        let fire = async {
+            async_std::task::sleep(std::time::Duration::from_millis(50)).await;
+            abort_link(lid).await
+        }
+        .fuse();
+        futures::pin_mut!(read, fire);
+
+        let resp = loop {
+            futures::select! {
+                r = read => break r,
+                e = fire => assert_eq!(e, xdr::DeviceErrorCode::NoError),
+            }
+        };
+        assert_eq!(resp.error, xdr::DeviceErrorCode::Abort);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+
+        // Unknown link
+        assert_eq!(
+            abort_link(xdr::DeviceLink(lid.0 + 1)).await,
+            xdr::DeviceErrorCode::InvalidLinkIdentifier
+        );
+    }
+
+    #[async_std::test]
+    async fn test_service_request() {
+        let mut devices = HashMap::new();
+        devices.insert(
+            "inst0".to_string(),
+            (EchoDevice::new_arc(), SharedLock::new()),
+        );
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let session = session(inner.clone());
+        let lid = create_link(&session, false).await.lid;
+
+        // Mock interrupt server
+        let listener = async_std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut args = Cursor::new(Vec::new());
+        u32::from(Ipv4Addr::LOCALHOST).write_xdr(&mut args).unwrap();
+        port.write_xdr(&mut args).unwrap();
+        DEVICE_INTR.write_xdr(&mut args).unwrap();
+        DEVICE_INTR_VERSION.write_xdr(&mut args).unwrap();
+        0u32.write_xdr(&mut args).unwrap(); // TCP
+        args.set_position(0);
+        let mut ret = Cursor::new(Vec::new());
+        session
+            .clone()
+            .call(
+                DEVICE_CORE,
+                DEVICE_CORE_VERSION,
+                vxi11::CREATE_INTR_CHAN,
+                &mut args,
+                &mut ret,
+            )
+            .await
+            .unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let resp: xdr::DeviceError = call(
+            &session,
+            vxi11::DEVICE_ENABLE_SRQ,
+            xdr::DeviceEnableSrqParms {
+                lid,
+                enable: true,
+                handle: Opaque(b"srq-handle".to_vec()),
+            },
+        )
+        .await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+
+        // Device requests service
+        inner.lock().await.status.send_status(0x40);
+
+        // Interrupt server receives device_intr_srq with the handle as argument
+        let mut mark = [0u8; 4];
+        stream.read_exact(&mut mark).await.unwrap();
+        let len = (u32::from_be_bytes(mark) & 0x7fff_ffff) as usize;
+        let mut call = vec![0u8; len];
+        stream.read_exact(&mut call).await.unwrap();
+        assert_eq!(&call[20..24], &DEVICE_INTR_SRQ.to_be_bytes());
+        assert!(call.ends_with(b"\0\0\0\x0asrq-handle\0\0"));
+    }
+
+    #[async_std::test]
+    async fn test_lock_unlock_clear() {
+        let mut devices = HashMap::new();
+        devices.insert(
+            "inst0".to_string(),
+            (EchoDevice::new_arc(), SharedLock::new()),
+        );
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let session1 = session(inner.clone());
+        let session2 = session(inner);
+        let lid1 = create_link(&session1, false).await.lid;
+        let lid2 = create_link(&session2, false).await.lid;
+
+        let lock = |lid| xdr::DeviceLockParms {
+            lid,
+            flags: xdr::DeviceFlags(0),
+            lock_timeout: 0,
+        };
+        let generic = |lid| xdr::DeviceGenericParms {
+            lid,
+            flags: xdr::DeviceFlags(0),
+            lock_timeout: 0,
+            io_timeout: 0,
+        };
+
+        // Response pending on second link
+        let _: xdr::DeviceWriteResp = call(
+            &session2,
+            vxi11::DEVICE_WRITE,
+            xdr::DeviceWriteParms {
+                lid: lid2,
+                io_timeout: 0,
+                lock_timeout: 0,
+                flags: xdr::DeviceFlags(0x08),
+                data: Opaque(b"*IDN?".to_vec()),
+            },
+        )
+        .await;
+
+        // First link locks
+        let resp: xdr::DeviceError = call(&session1, vxi11::DEVICE_LOCK, lock(lid1)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+
+        // Second link can neither lock nor clear
+        let resp: xdr::DeviceError = call(&session2, vxi11::DEVICE_LOCK, lock(lid2)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::DeviceLockedByAnotherLink);
+        let resp: xdr::DeviceError = call(&session2, vxi11::DEVICE_CLEAR, generic(lid2)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::DeviceLockedByAnotherLink);
+        assert_eq!(
+            session2.links.lock().await.get(&lid2.0).unwrap().out_buf,
+            b"*IDN?"
+        );
+
+        // Unlock, only once
+        let resp: xdr::DeviceError = call(&session1, vxi11::DEVICE_UNLOCK, lid1).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        let resp: xdr::DeviceError = call(&session1, vxi11::DEVICE_UNLOCK, lid1).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoLockHeldByThisLink);
+
+        // Second link can now lock and clear its pending response
+        let resp: xdr::DeviceError = call(&session2, vxi11::DEVICE_LOCK, lock(lid2)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        let resp: xdr::DeviceError = call(&session2, vxi11::DEVICE_CLEAR, generic(lid2)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        assert!(session2
+            .links
+            .lock()
+            .await
+            .get(&lid2.0)
+            .unwrap()
+            .out_buf
+            .is_empty());
+    }
+
+    /// Device recording calls made to it
+    #[derive(Default)]
+    struct RecordingDevice {
+        calls: Vec<String>,
+    }
+
+    impl Device for RecordingDevice {
+        fn execute<'a>(&mut self, cmd: &'a [u8]) -> Option<ExecuteOutput<'a>> {
+            Some(ExecuteOutput::Borrowed(cmd))
+        }
+
+        fn get_status(&mut self) -> Result<u8, DeviceError> {
+            self.calls.push("status".to_string());
+            Ok(0x42)
+        }
+
+        fn trigger(&mut self, source: Source) -> Result<(), DeviceError> {
+            self.calls.push(format!("trigger {source:?}"));
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), DeviceError> {
+            self.calls.push("clear".to_string());
+            Ok(())
+        }
+
+        fn set_remote(&mut self, remote: bool) -> Result<(), DeviceError> {
+            self.calls.push(format!("remote {remote}"));
+            Ok(())
+        }
+
+        fn docmd(&mut self, cmd: i32, data: &[u8]) -> Result<Vec<u8>, DeviceError> {
+            self.calls.push(format!("docmd {cmd}"));
+            match cmd {
+                1 => Ok(data.to_vec()),
+                _ => Err(DeviceError::NotSupported),
+            }
+        }
+    }
+
+    impl AsyncDevice for RecordingDevice {}
+
+    #[async_std::test]
+    async fn test_generic_procedures() {
+        let device = Arc::new(Mutex::new(RecordingDevice::default()));
+        let mut devices = HashMap::new();
+        devices.insert("inst0".to_string(), (device.clone(), SharedLock::new()));
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let session = session(inner);
+        let lid = create_link(&session, false).await.lid;
+
+        let generic = |lid| xdr::DeviceGenericParms {
+            lid,
+            flags: xdr::DeviceFlags(0x01),
+            lock_timeout: 1000,
+            io_timeout: 0,
+        };
+
+        for procedure in [
+            vxi11::DEVICE_TRIGGER,
+            vxi11::DEVICE_REMOTE,
+            vxi11::DEVICE_LOCAL,
+        ] {
+            let resp: xdr::DeviceError = call(&session, procedure, generic(lid)).await;
+            assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        }
+
+        // Status byte from device
+        let resp: xdr::DeviceReadStbResp =
+            call(&session, vxi11::DEVICE_READSTB, generic(lid)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        assert_eq!(resp.stb, 0x42);
+
+        // MAV is set while a response is pending
+        let _: xdr::DeviceWriteResp = call(
+            &session,
+            vxi11::DEVICE_WRITE,
+            xdr::DeviceWriteParms {
+                lid,
+                io_timeout: 0,
+                lock_timeout: 0,
+                flags: xdr::DeviceFlags(0x08),
+                data: Opaque(b"*IDN?".to_vec()),
+            },
+        )
+        .await;
+        let resp: xdr::DeviceReadStbResp =
+            call(&session, vxi11::DEVICE_READSTB, generic(lid)).await;
+        assert_eq!(resp.stb, 0x52);
+
+        assert_eq!(
+            device.lock().await.calls,
+            [
+                "trigger Bus",
+                "remote true",
+                "remote false",
+                "status",
+                "status"
+            ]
+        );
+
+        // Unknown link
+        for procedure in [
+            vxi11::DEVICE_TRIGGER,
+            vxi11::DEVICE_REMOTE,
+            vxi11::DEVICE_LOCAL,
+        ] {
+            let resp: xdr::DeviceError =
+                call(&session, procedure, generic(xdr::DeviceLink(lid.0 + 1))).await;
+            assert_eq!(resp.error, xdr::DeviceErrorCode::InvalidLinkIdentifier);
+        }
+        let resp: xdr::DeviceReadStbResp = call(
+            &session,
+            vxi11::DEVICE_READSTB,
+            generic(xdr::DeviceLink(lid.0 + 1)),
+        )
+        .await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::InvalidLinkIdentifier);
+    }
+
+    #[async_std::test]
+    async fn test_docmd() {
+        let device = Arc::new(Mutex::new(RecordingDevice::default()));
+        let mut devices = HashMap::new();
+        devices.insert("inst0".to_string(), (device.clone(), SharedLock::new()));
+        let inner = VxiInner::new(devices, StatusSender::new());
+        let session = session(inner);
+        let lid = create_link(&session, false).await.lid;
+
+        let docmd = |lid, cmd| xdr::DeviceDocmdParms {
+            lid,
+            flags: xdr::DeviceFlags(0),
+            io_timeout: 0,
+            lock_timeout: 0,
+            cmd,
+            network_order: false,
+            datasize: 1,
+            data_in: Opaque(b"abc".to_vec()),
+        };
+
+        let resp: xdr::DeviceDocmdResp = call(&session, vxi11::DEVICE_DOCMD, docmd(lid, 1)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::NoError);
+        assert_eq!(resp.data_out.0, b"abc");
+
+        let resp: xdr::DeviceDocmdResp = call(&session, vxi11::DEVICE_DOCMD, docmd(lid, 2)).await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::OperationNotSupported);
+        assert!(resp.data_out.0.is_empty());
+
+        let resp: xdr::DeviceDocmdResp = call(
+            &session,
+            vxi11::DEVICE_DOCMD,
+            docmd(xdr::DeviceLink(lid.0 + 1), 1),
+        )
+        .await;
+        assert_eq!(resp.error, xdr::DeviceErrorCode::InvalidLinkIdentifier);
+
+        assert_eq!(device.lock().await.calls, ["docmd 1", "docmd 2"]);
+    }
+
+    #[async_std::test]
+    async fn test_max_connections() {
+        let (core, _abort) = VxiServerBuilder::new()
+            .max_connections(1)
+            .device(
+                "inst0".to_string(),
+                EchoDevice::new_arc(),
+                SharedLock::new(),
+            )
+            .build(StatusSender::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(core.serve(listener));
+
+        // Occupies the only connection
+        let idle = TcpStream::connect(addr).await.unwrap();
+        task::sleep(std::time::Duration::from_millis(100)).await;
+
+        // Waits while at the limit
+        let mut pending = task::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            StreamRpcClient::new(stream, DEVICE_CORE, DEVICE_CORE_VERSION)
+                .null()
+                .await
+        });
+        assert!(
+            async_std::future::timeout(std::time::Duration::from_millis(200), &mut pending)
+                .await
+                .is_err()
+        );
+
+        // Served once the idle connection closes
+        drop(idle);
+        async_std::future::timeout(std::time::Duration::from_secs(1), pending)
+            .await
+            .expect("connection not accepted")
+            .unwrap();
+    }
+}
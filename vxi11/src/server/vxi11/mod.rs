@@ -1,6 +1,9 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use async_std::{net::ToSocketAddrs, task::JoinHandle};
+use async_std::{
+    net::ToSocketAddrs,
+    task::{self, JoinHandle},
+};
 
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
@@ -8,6 +11,7 @@ use futures::{
 };
 use lxi_device::{
     lock::{LockHandle, SharedLock, SharedLockError, SpinMutex},
+    stats::MessageStats,
     status::Sender as StatusSender,
     DeviceError as LxiDeviceError,
 };
@@ -19,6 +23,7 @@ use crate::{
         portmapper::{xdr::Mapping, PORTMAPPER_PROT_TCP},
         vxi11::xdr,
     },
+    server::portmapper::StaticPortMap,
 };
 
 pub(crate) mod abort_service;
@@ -103,11 +108,6 @@ impl<DEV> Link<DEV> {
         )
     }
 
-    fn clear(&mut self) {
-        self.in_buf.clear();
-        self.out_buf.clear();
-    }
-
     fn close(&mut self) {
         log::trace!("Link {} closed", self.id);
         // Release any held locks
@@ -167,6 +167,10 @@ pub struct VxiServerBuilder<DEV> {
     core_port: u16,
     async_port: u16,
     devices: DeviceMap<DEV>,
+    stats: Option<Arc<MessageStats>>,
+    max_record_size: usize,
+    max_connections: usize,
+    portmap_addr: Option<SocketAddr>,
 }
 
 impl<DEV> Default for VxiServerBuilder<DEV> {
@@ -175,6 +179,10 @@ impl<DEV> Default for VxiServerBuilder<DEV> {
             core_port: 4322,
             async_port: 4323,
             devices: Default::default(),
+            stats: None,
+            max_record_size: 1024 * 1024,
+            max_connections: 10,
+            portmap_addr: None,
         }
     }
 }
@@ -196,6 +204,36 @@ impl<DEV> VxiServerBuilder<DEV> {
         self
     }
 
+    /// Collect core channel record size statistics into `stats`.
+    pub fn stats(mut self, stats: Arc<MessageStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Set the largest RPC record accepted on the core channel.
+    ///
+    /// Clients sending larger records are disconnected before the record is allocated.
+    pub fn max_record_size(mut self, max_record_size: usize) -> Self {
+        self.max_record_size = max_record_size;
+        self
+    }
+
+    /// Set the number of connections served at once by each of the core and async channels.
+    ///
+    /// New connections wait to be accepted while at the limit.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Start a [VxiServerBuilder::static_portmap] on `addr` (TCP and UDP) when the server is built.
+    ///
+    /// Used when there is no system portmap/rpcbind, typically on [crate::common::portmapper::PORTMAPPER_PORT].
+    pub fn serve_portmap(mut self, addr: SocketAddr) -> Self {
+        self.portmap_addr = Some(addr);
+        self
+    }
+
     /// Register VXI server using portmap/rpcbind
     pub async fn register_portmap(self, addrs: impl ToSocketAddrs) -> Result<Self, RpcError> {
         if self.async_port == 0 || self.core_port == 0 {
@@ -229,6 +267,26 @@ impl<DEV> VxiServerBuilder<DEV> {
         }
     }
 
+    /// Create a static portmapper answering GETPORT for the core and async services.
+    ///
+    /// Used when there is no system portmap/rpcbind to register with, serve it on [crate::common::portmapper::PORTMAPPER_PORT].
+    pub fn static_portmap(&self) -> Arc<StaticPortMap<2>> {
+        StaticPortMap::new([
+            Mapping::new(
+                DEVICE_CORE,
+                DEVICE_CORE_VERSION,
+                PORTMAPPER_PROT_TCP,
+                self.core_port as u32,
+            ),
+            Mapping::new(
+                DEVICE_ASYNC,
+                DEVICE_ASYNC_VERSION,
+                PORTMAPPER_PROT_TCP,
+                self.async_port as u32,
+            ),
+        ])
+    }
+
     pub fn device(
         mut self,
         subaddr: String,
@@ -243,16 +301,29 @@ impl<DEV> VxiServerBuilder<DEV> {
         self,
         status: StatusSender,
     ) -> (Arc<VxiCoreServer<DEV>>, Arc<VxiAsyncServer<DEV>>) {
+        if let Some(addr) = self.portmap_addr {
+            let portmap = self.static_portmap();
+            task::spawn(async move {
+                if let Err(err) = portmap.bind(addr).await {
+                    log::error!("Failed to serve portmap on {}: {}", addr, err);
+                }
+            });
+        }
+
         let inner = VxiInner::new(self.devices, status);
         (
             Arc::new(VxiCoreServer {
                 inner: inner.clone(),
                 async_port: self.async_port,
                 max_recv_size: 128 * 1024,
+                stats: self.stats,
+                max_record_size: self.max_record_size,
+                max_connections: self.max_connections,
             }),
             Arc::new(VxiAsyncServer {
                 inner,
                 async_port: self.async_port,
+                max_connections: self.max_connections,
             }),
         )
     }
@@ -54,3 +54,48 @@ async fn portmap_udp_null() {
         .unwrap();
     let _ = client.null().await.unwrap();
 }
+
+#[async_std::test]
+async fn portmap_tcp_dump() {
+    use async_std::{net::TcpListener, task};
+    use lxi_vxi11::server::portmapper::StaticPortMap;
+
+    let mappings = [
+        Mapping::new(PORTMAPPER_PROG, PORTMAPPER_VERS, PORTMAPPER_PROT_TCP, 111),
+        Mapping::new(PORTMAPPER_PROG, PORTMAPPER_VERS, PORTMAPPER_PROT_UDP, 111),
+        Mapping::new(0x0607AF, 1, PORTMAPPER_PROT_TCP, 4322),
+    ];
+
+    // Serve a stub portmapper on a random port
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(StaticPortMap::new(mappings).serve_tcp(listener));
+
+    let mut client = PortMapperClient::connect_tcp(addr).await.unwrap();
+    let dump = client.dump().await.unwrap();
+    assert_eq!(dump, mappings.to_vec());
+}
+
+#[async_std::test]
+async fn portmap_udp_local_addr() {
+    use async_std::{net::UdpSocket, task};
+    use lxi_vxi11::server::portmapper::StaticPortMap;
+
+    // Serve a stub portmapper on a random port
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    task::spawn(StaticPortMap::new([]).serve_udp(socket));
+
+    // Pick a free local port to bind client to
+    let local = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut client = PortMapperClient::connect_udp_from(local, addr)
+        .await
+        .unwrap();
+    assert_eq!(client.local_addr().unwrap(), local);
+    client.null().await.unwrap();
+}
@@ -0,0 +1,130 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use async_std::{
+    future,
+    net::{TcpListener, UdpSocket},
+    task,
+};
+use lxi_device::{status::Sender as StatusSender, util::EchoDevice};
+use lxi_vxi11::{client::portmapper::prelude::*, server::vxi11::prelude::*};
+
+#[async_std::test]
+async fn builder_portmap_getport() {
+    let builder = VxiServerBuilder::<EchoDevice>::new()
+        .core_port(14322)
+        .async_port(14323);
+    let portmap = builder.static_portmap();
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let tcp_addr = listener.local_addr().unwrap();
+    let udp_addr = socket.local_addr().unwrap();
+    task::spawn(portmap.clone().serve_tcp(listener));
+    task::spawn(portmap.serve_udp(socket));
+
+    let core = Mapping::new(DEVICE_CORE, DEVICE_CORE_VERSION, PORTMAPPER_PROT_TCP, 0);
+    let abort = Mapping::new(DEVICE_ASYNC, DEVICE_ASYNC_VERSION, PORTMAPPER_PROT_TCP, 0);
+    let unknown = Mapping::new(DEVICE_INTR, DEVICE_INTR_VERSION, PORTMAPPER_PROT_TCP, 0);
+
+    let mut client = PortMapperClient::connect_tcp(tcp_addr).await.unwrap();
+    assert_eq!(client.getport(core).await.unwrap(), 14322);
+    assert_eq!(client.getport(abort).await.unwrap(), 14323);
+    assert_eq!(client.getport(unknown).await.unwrap(), 0);
+
+    let mut client = PortMapperClient::connect_udp(udp_addr).await.unwrap();
+    assert_eq!(client.getport(core).await.unwrap(), 14322);
+}
+
+/// Raw portmap GETPORT call, mapping `prog`/`vers` over TCP
+fn getport_call(xid: u32, prog: u32, vers: u32) -> Vec<u8> {
+    [
+        xid,
+        0, // Call
+        2, // RPC version
+        PORTMAPPER_PROG,
+        PORTMAPPER_VERS,
+        3, // GETPORT
+        0, // Auth none
+        0,
+        0, // Verf none
+        0,
+        prog,
+        vers,
+        PORTMAPPER_PROT_TCP,
+        0,
+    ]
+    .iter()
+    .flat_map(|x| x.to_be_bytes())
+    .collect()
+}
+
+#[async_std::test]
+async fn broadcast_getport() {
+    let builder = VxiServerBuilder::<EchoDevice>::new()
+        .core_port(14324)
+        .async_port(14325);
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.unwrap();
+    let port = socket.local_addr().unwrap().port();
+    task::spawn(builder.static_portmap().serve_udp(socket));
+
+    let client = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.unwrap();
+    client.set_broadcast(true).unwrap();
+    let broadcast = SocketAddr::from((Ipv4Addr::new(127, 255, 255, 255), port));
+
+    // Garbage does not stop the responder
+    client.send_to(b"garbage", broadcast).await.unwrap();
+
+    client
+        .send_to(
+            &getport_call(0x1234, DEVICE_CORE, DEVICE_CORE_VERSION),
+            broadcast,
+        )
+        .await
+        .unwrap();
+    let mut buf = [0u8; 64];
+    let (n, _peer) = client.recv_from(&mut buf).await.unwrap();
+
+    assert_eq!(n, 28);
+    assert_eq!(buf[..4], 0x1234u32.to_be_bytes());
+    // Reply
+    assert_eq!(buf[4..8], 1u32.to_be_bytes());
+    // Accepted, verf none, success
+    assert_eq!(buf[8..24], [0; 16]);
+    assert_eq!(buf[24..28], 14324u32.to_be_bytes());
+}
+
+#[async_std::test]
+async fn builder_serve_portmap() {
+    // Find a free port
+    let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let _server = VxiServerBuilder::<EchoDevice>::new()
+        .core_port(14326)
+        .async_port(14327)
+        .serve_portmap(addr)
+        .build(StatusSender::new());
+
+    // Portmap is started in the background
+    let mut client = future::timeout(Duration::from_secs(1), async {
+        loop {
+            match PortMapperClient::connect_tcp(addr).await {
+                Ok(client) => break client,
+                Err(_) => task::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    })
+    .await
+    .unwrap();
+    let core = Mapping::new(DEVICE_CORE, DEVICE_CORE_VERSION, PORTMAPPER_PROT_TCP, 0);
+    assert_eq!(client.getport(core).await.unwrap(), 14326);
+
+    let mut client = PortMapperClient::connect_udp(addr).await.unwrap();
+    assert_eq!(client.getport(core).await.unwrap(), 14326);
+}